@@ -1,17 +1,35 @@
 use std::{
-	collections::HashMap,
 	fmt::Debug,
 	future::Future,
+	io::Error as IoError,
 	pin::Pin,
-	sync::Arc,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
 };
 
+use hyper::{service::Service, Body, Request as HyperRequest};
+use regex::Regex;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+#[cfg(feature = "tower")]
+use crate::tower::{AppMakeService, AppService};
+#[cfg(feature = "tracing")]
+use crate::trace_context;
+#[cfg(feature = "websocket")]
+use crate::websocket::{self, WebSocket, WebSocketHandler, WebSocketHandlerFn};
 use crate::{
 	context::Context,
+	default_middlewares::timeout::{self, DEFAULT_TIMEOUT_STATUS},
 	error::Error,
 	http_method::HttpMethod,
 	middleware::Middleware,
-	middleware_handler::MiddlewareHandler,
+	middleware_handler::{get_path_match, get_url_params, MiddlewareHandler},
+	proxy_protocol::ProxyProtocol,
+	pub_sub::PubSub,
+	service::{BoxError, MountedService, ServiceResponse},
+	trusted_proxies::{ForwardedHeaders, TrustedProxies},
+	BodyError,
 	Request,
 	Response,
 };
@@ -19,9 +37,69 @@ use crate::{
 type ContextGeneratorFn<TContext, TState> = fn(Request, &TState) -> TContext;
 type ErrorHandlerFn<TErrorData> = fn(Response, Error<TErrorData>) -> Response;
 
+/// What runs once every matching middleware in the stack has called `next`
+#[derive(Clone)]
+pub(crate) enum Endpoint {
+	NotFound,
+	Service(MountedService),
+	#[cfg(feature = "websocket")]
+	WebSocket {
+		index: usize,
+		path_match: Regex,
+	},
+}
+
+/// A setting that applies to a single route, such as its timeout
+#[derive(Clone)]
+struct RouteSetting<TValue> {
+	method: HttpMethod,
+	mounted_url: String,
+	path_match: Regex,
+	value: TValue,
+}
+
+impl<TValue> RouteSetting<TValue>
+where
+	TValue: Copy,
+{
+	fn new(method: HttpMethod, path: &str, value: TValue) -> Self {
+		let (mounted_url, path_match) = get_path_match(path, true);
+		RouteSetting {
+			method,
+			mounted_url,
+			path_match,
+			value,
+		}
+	}
+
+	/// Moves the setting under the base path of a sub app
+	fn with_base_path(self, base_path: &str) -> Self {
+		RouteSetting::new(
+			self.method,
+			&format!("{}{}", base_path, self.mounted_url),
+			self.value,
+		)
+	}
+
+	/// Returns the value of the first setting matching the route
+	fn find(
+		settings: &[RouteSetting<TValue>],
+		method: &HttpMethod,
+		path: &str,
+	) -> Option<TValue> {
+		settings
+			.iter()
+			.find(|setting| {
+				&setting.method == method && setting.path_match.is_match(path)
+			})
+			.map(|setting| setting.value)
+	}
+}
+
 fn chained_run<TContext, TMiddleware, TErrorData>(
 	mut context: TContext,
 	nodes: Arc<Vec<MiddlewareHandler<TContext, TMiddleware, TErrorData>>>,
+	endpoint: Endpoint,
 	i: usize,
 ) -> Pin<Box<dyn Future<Output = Result<TContext, Error<TErrorData>>> + Send>>
 where
@@ -33,37 +111,86 @@ where
 	Box::pin(async move {
 		if let Some(m) = nodes.clone().get(i) {
 			// add populating the url parameters here
-			let mut url_params = HashMap::new();
-			if let Some(captures) = m.path_match.captures(&context.get_path()) {
-				for var in m.path_match.capture_names() {
-					if var.is_none() {
-						continue;
-					}
-					let var = var.unwrap();
-					let value = captures.name(var);
-					if let Some(value) = value {
-						url_params.insert(
-							var.to_string(),
-							value.as_str().to_string(),
+			context.get_request_mut().params =
+				get_url_params(&m.path_match, &context.get_path());
+			let record_timing = context.get_request().record_middleware_timings;
+			let mounted_url = m.mounted_url.clone();
+			// The time spent in the rest of the chain, which isn't counted
+			// towards the middleware's own timing. Only tracked when timings
+			// are recorded, to keep it off the path of every other request.
+			let time_in_next =
+				record_timing.then(|| Arc::new(Mutex::new(Duration::ZERO)));
+			let start = Instant::now();
+			let future = m.handler.run_middleware(
+				context,
+				Box::new({
+					let time_in_next = time_in_next.clone();
+					move |context| {
+						let next = chained_run(
+							context,
+							nodes.clone(),
+							endpoint.clone(),
+							i + 1,
 						);
+						let Some(time_in_next) = time_in_next.clone() else {
+							return next;
+						};
+						Box::pin(async move {
+							let start = Instant::now();
+							let result = next.await;
+							*time_in_next.lock().unwrap() += start.elapsed();
+							result
+						})
 					}
+				}),
+			);
+			// The span of a middleware includes the time spent in the ones
+			// after it, since they run when it calls `next`
+			#[cfg(feature = "tracing")]
+			let future = future.instrument(tracing::info_span!(
+				"middleware",
+				index = i,
+				route = %mounted_url,
+			));
+			let mut result = future.await;
+
+			if let Some(time_in_next) = time_in_next {
+				if let Ok(context) = &mut result {
+					let time_in_next = *time_in_next.lock().unwrap();
+					context.get_response_mut().add_timing(
+						&format!("middleware-{}", i),
+						start.elapsed().saturating_sub(time_in_next),
+						Some(&mounted_url),
+					);
 				}
 			}
-			context.get_request_mut().params = url_params;
-			m.handler
-				.run_middleware(
-					context,
-					Box::new(move |context| {
-						chained_run(context, nodes.clone(), i + 1)
-					}),
-				)
-				.await
+			result
 		} else {
-			let method = context.get_method().to_string();
-			let path = context.get_path();
-			context
-				.status(404)
-				.body(&format!("Cannot {} route {}", method, path));
+			match endpoint {
+				Endpoint::NotFound => {
+					let method = context.get_method().to_string();
+					let path = context.get_path();
+					context
+						.status(404)
+						.body(&format!("Cannot {} route {}", method, path));
+				}
+				Endpoint::Service(service) => {
+					context.get_request_mut().params = get_url_params(
+						&service.path_match,
+						&context.get_path(),
+					);
+					return service.serve(context).await;
+				}
+				#[cfg(feature = "websocket")]
+				Endpoint::WebSocket { index, path_match } => {
+					context.get_request_mut().params =
+						get_url_params(&path_match, &context.get_path());
+					if websocket::accept_handshake(&mut context) {
+						context.get_response_mut().websocket_handler =
+							Some(index);
+					}
+				}
+			}
 			Ok(context)
 		}
 	})
@@ -79,6 +206,16 @@ where
 	context_generator: ContextGeneratorFn<TContext, TState>,
 	state: TState,
 	pub(crate) error_handler: Option<ErrorHandlerFn<TErrorData>>,
+	pub_sub: PubSub,
+	trusted_proxies: TrustedProxies,
+	forwarded_headers: ForwardedHeaders,
+	proxy_protocol: ProxyProtocol,
+	cancel_on_disconnect: bool,
+	timeout: Option<Duration>,
+	timeout_status: u16,
+	route_timeouts: Vec<RouteSetting<Duration>>,
+	body_limit: Option<usize>,
+	route_body_limits: Vec<RouteSetting<usize>>,
 
 	get_stack: Vec<MiddlewareHandler<TContext, TMiddleware, TErrorData>>,
 	post_stack: Vec<MiddlewareHandler<TContext, TMiddleware, TErrorData>>,
@@ -89,6 +226,10 @@ where
 	connect_stack: Vec<MiddlewareHandler<TContext, TMiddleware, TErrorData>>,
 	patch_stack: Vec<MiddlewareHandler<TContext, TMiddleware, TErrorData>>,
 	trace_stack: Vec<MiddlewareHandler<TContext, TMiddleware, TErrorData>>,
+	service_stack: Vec<MountedService>,
+
+	#[cfg(feature = "websocket")]
+	websocket_stack: Vec<WebSocketHandler<TContext>>,
 }
 
 impl<TContext, TMiddleware, TState, TErrorData>
@@ -108,6 +249,16 @@ where
 			context_generator,
 			state,
 			error_handler: None,
+			pub_sub: PubSub::default(),
+			trusted_proxies: TrustedProxies::None,
+			forwarded_headers: ForwardedHeaders::XForwarded,
+			proxy_protocol: ProxyProtocol::Disabled,
+			cancel_on_disconnect: true,
+			timeout: None,
+			timeout_status: DEFAULT_TIMEOUT_STATUS,
+			route_timeouts: vec![],
+			body_limit: None,
+			route_body_limits: vec![],
 
 			get_stack: vec![],
 			post_stack: vec![],
@@ -118,6 +269,10 @@ where
 			connect_stack: vec![],
 			patch_stack: vec![],
 			trace_stack: vec![],
+			service_stack: vec![],
+
+			#[cfg(feature = "websocket")]
+			websocket_stack: vec![],
 		}
 	}
 
@@ -136,6 +291,150 @@ where
 		self.error_handler = None;
 	}
 
+	/// Returns the pub/sub hub of the app, which is also reachable from every
+	/// context through `Context::get_pub_sub`
+	pub fn get_pub_sub(&self) -> &PubSub {
+		&self.pub_sub
+	}
+
+	pub fn set_pub_sub(&mut self, pub_sub: PubSub) {
+		self.pub_sub = pub_sub;
+	}
+
+	pub fn get_trusted_proxies(&self) -> &TrustedProxies {
+		&self.trusted_proxies
+	}
+
+	/// Sets the proxies that are trusted to report the client's address,
+	/// protocol and host through the forwarding headers
+	pub fn set_trusted_proxies(&mut self, trusted_proxies: TrustedProxies) {
+		self.trusted_proxies = trusted_proxies;
+	}
+
+	pub fn get_forwarded_headers(&self) -> ForwardedHeaders {
+		self.forwarded_headers
+	}
+
+	/// Sets which forwarding headers the trusted proxies set. The headers of
+	/// the other family are ignored, as they could have been sent by the
+	/// client.
+	pub fn set_forwarded_headers(
+		&mut self,
+		forwarded_headers: ForwardedHeaders,
+	) {
+		self.forwarded_headers = forwarded_headers;
+	}
+
+	pub fn get_proxy_protocol(&self) -> ProxyProtocol {
+		self.proxy_protocol
+	}
+
+	/// Sets whether connections accepted by `listen` start with a PROXY
+	/// protocol header. The client address from the header is used as the
+	/// socket address of every request made on the connection. The header is
+	/// trusted from any peer, so the app must only be reachable through the
+	/// proxy.
+	pub fn set_proxy_protocol(&mut self, proxy_protocol: ProxyProtocol) {
+		self.proxy_protocol = proxy_protocol;
+	}
+
+	pub fn get_cancel_on_disconnect(&self) -> bool {
+		self.cancel_on_disconnect
+	}
+
+	/// Sets whether the middlewares handling a request are dropped when the
+	/// client disconnects before the response is sent, which is the default.
+	/// If disabled, the middlewares run to completion in a separate task
+	/// and can use the cancellation token of the request to stop early.
+	pub fn set_cancel_on_disconnect(&mut self, cancel_on_disconnect: bool) {
+		self.cancel_on_disconnect = cancel_on_disconnect;
+	}
+
+	pub fn get_timeout(&self) -> Option<Duration> {
+		self.timeout
+	}
+
+	/// Sets how long the middlewares handling a request may run before
+	/// they're aborted and an error with the timeout status is returned.
+	/// Routes with their own timeout use that instead.
+	pub fn set_timeout(&mut self, timeout: Duration) {
+		self.timeout = Some(timeout);
+	}
+
+	pub fn remove_timeout(&mut self) {
+		self.timeout = None;
+	}
+
+	pub fn get_timeout_status(&self) -> u16 {
+		self.timeout_status
+	}
+
+	/// Sets the status of the error returned when a request times out, which
+	/// is 503 by default. The error is handled by the error handler like any
+	/// other error.
+	pub fn set_timeout_status(&mut self, status: u16) {
+		self.timeout_status = status;
+	}
+
+	/// Sets the timeout of a route, which replaces the timeout of the app for
+	/// requests to that route
+	pub fn set_route_timeout(
+		&mut self,
+		method: HttpMethod,
+		path: &str,
+		timeout: Duration,
+	) {
+		self.route_timeouts
+			.push(RouteSetting::new(method, path, timeout));
+	}
+
+	pub fn get_body_limit(&self) -> Option<usize> {
+		self.body_limit
+	}
+
+	/// Sets the maximum size of request bodies, in bytes. Requests with a
+	/// larger `Content-Length` are rejected before any middleware runs, and
+	/// reading a body stops with an error once it exceeds the limit. Either
+	/// way, the client gets a 413 through the error handler. Routes with
+	/// their own limit use that instead.
+	pub fn set_body_limit(&mut self, limit: usize) {
+		self.body_limit = Some(limit);
+	}
+
+	pub fn remove_body_limit(&mut self) {
+		self.body_limit = None;
+	}
+
+	/// Sets the maximum size of request bodies for a route, which replaces
+	/// the limit of the app for requests to that route
+	pub fn set_route_body_limit(
+		&mut self,
+		method: HttpMethod,
+		path: &str,
+		limit: usize,
+	) {
+		self.route_body_limits
+			.push(RouteSetting::new(method, path, limit));
+	}
+
+	/// Converts the app into a tower `Service` that handles hyper requests
+	#[cfg(feature = "tower")]
+	pub fn into_service(
+		self,
+	) -> AppService<TContext, TMiddleware, TState, TErrorData> {
+		AppService::new(self)
+	}
+
+	/// Converts the app into a service that can be given to
+	/// `hyper::Server::serve`, which uses the address of each connection as
+	/// the socket address of its requests
+	#[cfg(feature = "tower")]
+	pub fn into_make_service(
+		self,
+	) -> AppMakeService<TContext, TMiddleware, TState, TErrorData> {
+		AppMakeService::new(self)
+	}
+
 	pub fn get<const MIDDLEWARE_LENGTH: usize>(
 		&mut self,
 		path: &str,
@@ -240,6 +539,36 @@ where
 		});
 	}
 
+	/// Mounts a WebSocket endpoint on the path. Middlewares mounted on the
+	/// path using `use_middleware` run before the handshake, so they can
+	/// reject the connection (for example, for authentication). Once the
+	/// handshake completes, the handler is given the context and the
+	/// connection.
+	#[cfg(feature = "websocket")]
+	pub fn websocket(
+		&mut self,
+		path: &str,
+		handler: WebSocketHandlerFn<TContext>,
+	) {
+		self.websocket_stack
+			.push(WebSocketHandler::new(path, handler));
+	}
+
+	/// Mounts a hyper (or tower) service on the path. Requests for the path
+	/// or anything under it that aren't handled by the routes of the app are
+	/// handed to the service, with the mounted path removed from the URI.
+	/// Middlewares mounted on the path using `use_middleware` run before the
+	/// service, and its response is sent like any other response.
+	pub fn mount_service<TService>(&mut self, path: &str, service: TService)
+	where
+		TService: Service<HyperRequest<Body>> + Clone + Send + Sync + 'static,
+		TService::Response: ServiceResponse,
+		TService::Error: Into<BoxError>,
+		TService::Future: Send,
+	{
+		self.service_stack.push(MountedService::new(path, service));
+	}
+
 	pub fn use_middleware<const MIDDLEWARE_LENGTH: usize>(
 		&mut self,
 		path: &str,
@@ -400,21 +729,234 @@ where
 				)
 			},
 		));
+
+		self.route_timeouts.extend(
+			sub_app
+				.route_timeouts
+				.into_iter()
+				.map(|setting| setting.with_base_path(&base_path)),
+		);
+		self.route_body_limits.extend(
+			sub_app
+				.route_body_limits
+				.into_iter()
+				.map(|setting| setting.with_base_path(&base_path)),
+		);
+
+		self.service_stack
+			.extend(sub_app.service_stack.into_iter().map(|service| {
+				MountedService::new_with_handler(
+					&format!("{}{}", base_path, service.mounted_url),
+					service.handler,
+				)
+			}));
+
+		#[cfg(feature = "websocket")]
+		self.websocket_stack
+			.extend(sub_app.websocket_stack.into_iter().map(|handler| {
+				WebSocketHandler::new(
+					&format!("{}{}", base_path, handler.mounted_url),
+					handler.handler,
+				)
+			}));
 	}
 
 	pub async fn resolve(
 		&self,
-		context: TContext,
+		mut context: TContext,
 	) -> Result<TContext, Error<TErrorData>> {
 		let stack =
 			self.get_middleware_stack(context.get_method(), context.get_path());
-		chained_run(context, Arc::new(stack), 0).await
+		let endpoint =
+			self.get_endpoint(context.get_method(), &context.get_path());
+		context.get_request_mut().route =
+			self.get_route_pattern(&stack, &endpoint);
+
+		#[cfg(feature = "tracing")]
+		{
+			let span = trace_context::request_span(&context);
+			let traceparent =
+				context.get_request().get_trace_context().to_traceparent();
+			let result = self
+				.run_chain(context, stack, endpoint)
+				.instrument(span.clone())
+				.await;
+			trace_context::record_status(&span, &result);
+			trace_context::set_traceparent(&traceparent, result)
+		}
+		#[cfg(not(feature = "tracing"))]
+		self.run_chain(context, stack, endpoint).await
+	}
+
+	async fn run_chain(
+		&self,
+		mut context: TContext,
+		stack: Vec<MiddlewareHandler<TContext, TMiddleware, TErrorData>>,
+		endpoint: Endpoint,
+	) -> Result<TContext, Error<TErrorData>> {
+		// 100-continue is the only expectation defined, and hyper answers it
+		// once the body is read
+		if let Some(expect) = context.get_request().get_header("Expect") {
+			if !expect.eq_ignore_ascii_case("100-continue") {
+				return Err(Error::<TErrorData>::new(Box::new(
+					IoError::other(format!(
+						"unsupported expectation `{}`",
+						expect
+					)),
+				))
+				.status(417));
+			}
+		}
+
+		let body_limit = self
+			.get_route_body_limit(context.get_method(), &context.get_path());
+		if let Some(limit) = body_limit {
+			// Reject bodies that are known to be too large before anything
+			// reads them
+			if context.get_request().get_length() > limit as u128 {
+				return Err(BodyError::TooLarge(limit).into());
+			}
+		}
+		context.get_request_mut().body_limit = body_limit;
+
+		let timeout =
+			self.get_route_timeout(context.get_method(), &context.get_path());
+		let cancellation_token = context.get_cancellation_token().clone();
+
+		let chain = chained_run(context, Arc::new(stack), endpoint, 0);
+		if let Some(timeout) = timeout {
+			timeout::run_with_timeout(
+				timeout,
+				self.timeout_status,
+				cancellation_token,
+				chain,
+			)
+			.await
+		} else {
+			chain.await
+		}
+	}
+
+	/// Takes the response out of a resolved context. If the context accepted
+	/// a WebSocket handshake, the WebSocket handler is started once the
+	/// connection has been upgraded.
+	pub(crate) fn take_response(&self, context: TContext) -> Response {
+		#[cfg(feature = "websocket")]
+		let mut context = context;
+		#[cfg(feature = "websocket")]
+		if let Some(index) = context.get_response_mut().websocket_handler.take()
+		{
+			let handler = self.websocket_stack[index].handler;
+			let response = std::mem::take(context.get_response_mut());
+			let on_upgrade = context.get_request_mut().on_upgrade();
+			tokio::spawn(async move {
+				match on_upgrade.await {
+					Ok(upgraded) => {
+						handler(
+							context,
+							WebSocket::from_upgraded(upgraded).await,
+						)
+						.await
+					}
+					Err(err) => {
+						log::error!("Unable to upgrade to a WebSocket: {}", err)
+					}
+				}
+			});
+			return response;
+		}
+
+		context.take_response()
 	}
 
-	pub(crate) fn generate_context(&self, request: Request) -> TContext {
+	pub(crate) fn generate_context(&self, mut request: Request) -> TContext {
+		request.pub_sub = self.pub_sub.clone();
+		request.apply_trusted_proxies(
+			&self.trusted_proxies,
+			self.forwarded_headers,
+		);
 		(self.context_generator)(request, self.get_state())
 	}
 
+	fn get_route_timeout(
+		&self,
+		method: &HttpMethod,
+		path: &str,
+	) -> Option<Duration> {
+		RouteSetting::find(&self.route_timeouts, method, path).or(self.timeout)
+	}
+
+	fn get_route_body_limit(
+		&self,
+		method: &HttpMethod,
+		path: &str,
+	) -> Option<usize> {
+		RouteSetting::find(&self.route_body_limits, method, path)
+			.or(self.body_limit)
+	}
+
+	/// Returns the pattern of the route that handles requests with the method
+	/// and path, such as `/users/:id`, if any route handles them
+	pub(crate) fn get_route(
+		&self,
+		method: &HttpMethod,
+		path: &str,
+	) -> Option<String> {
+		let stack = self.get_middleware_stack(method, path.to_string());
+		let endpoint = self.get_endpoint(method, path);
+		self.get_route_pattern(&stack, &endpoint)
+	}
+
+	/// Returns the pattern of the route that handles the request, such as
+	/// `/users/:id`, for the request's endpoint. When several routes match,
+	/// the first one registered handles the request.
+	fn get_route_pattern(
+		&self,
+		stack: &[MiddlewareHandler<TContext, TMiddleware, TErrorData>],
+		endpoint: &Endpoint,
+	) -> Option<String> {
+		if let Some(handler) = stack.iter().find(|handler| handler.is_endpoint)
+		{
+			return Some(handler.mounted_url.clone());
+		}
+		match endpoint {
+			Endpoint::NotFound => None,
+			Endpoint::Service(service) => Some(service.mounted_url.clone()),
+			#[cfg(feature = "websocket")]
+			Endpoint::WebSocket { index, .. } => {
+				Some(self.websocket_stack[*index].mounted_url.clone())
+			}
+		}
+	}
+
+	#[allow(unused_variables)]
+	fn get_endpoint(&self, method: &HttpMethod, path: &str) -> Endpoint {
+		#[cfg(feature = "websocket")]
+		if method == &HttpMethod::Get {
+			if let Some((index, handler)) = self
+				.websocket_stack
+				.iter()
+				.enumerate()
+				.find(|(_, handler)| handler.is_match(path))
+			{
+				return Endpoint::WebSocket {
+					index,
+					path_match: handler.path_match.clone(),
+				};
+			}
+		}
+
+		if let Some(service) = self
+			.service_stack
+			.iter()
+			.find(|service| service.is_match(path))
+		{
+			return Endpoint::Service(service.clone());
+		}
+
+		Endpoint::NotFound
+	}
+
 	fn get_middleware_stack(
 		&self,
 		method: &HttpMethod,
@@ -469,6 +1011,16 @@ where
 			context_generator: self.context_generator,
 			state: self.state.clone(),
 			error_handler: self.error_handler,
+			pub_sub: self.pub_sub.clone(),
+			trusted_proxies: self.trusted_proxies.clone(),
+			forwarded_headers: self.forwarded_headers,
+			proxy_protocol: self.proxy_protocol,
+			cancel_on_disconnect: self.cancel_on_disconnect,
+			timeout: self.timeout,
+			timeout_status: self.timeout_status,
+			route_timeouts: self.route_timeouts.clone(),
+			body_limit: self.body_limit,
+			route_body_limits: self.route_body_limits.clone(),
 
 			get_stack: self.get_stack.clone(),
 			post_stack: self.post_stack.clone(),
@@ -479,6 +1031,10 @@ where
 			connect_stack: self.connect_stack.clone(),
 			patch_stack: self.patch_stack.clone(),
 			trace_stack: self.trace_stack.clone(),
+			service_stack: self.service_stack.clone(),
+
+			#[cfg(feature = "websocket")]
+			websocket_stack: self.websocket_stack.clone(),
 		}
 	}
 }
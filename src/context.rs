@@ -1,13 +1,31 @@
 use std::{
+	error::Error as StdError,
+	io::Result as IoResult,
 	net::IpAddr,
-	str::{self, Utf8Error},
+	time::Duration,
 };
 
-use serde::Serialize;
+use futures::Stream;
+use hyper::{body::Bytes, upgrade::OnUpgrade, Body};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
+use tokio::{fs::File, io::AsyncRead};
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "tracing")]
+use crate::TraceContext;
+use crate::{
+	cookie::Cookie,
+	headers::Header,
+	pub_sub::PubSub,
+	query::{QueryError, QueryMap},
+	request::{BodyError, Request},
+	response::Response,
+	sse::{self, SseSender},
+	HttpMethod,
+};
 
-use crate::{cookie::Cookie, request::Request, response::Response, HttpMethod};
-
+#[async_trait::async_trait]
 pub trait Context {
 	fn get_request(&self) -> &Request;
 	fn get_request_mut(&mut self) -> &mut Request;
@@ -15,8 +33,14 @@ pub trait Context {
 	fn take_response(self) -> Response;
 	fn get_response_mut(&mut self) -> &mut Response;
 
-	fn get_body(&self) -> Result<String, Utf8Error> {
-		self.get_request().get_body()
+	async fn get_body(&mut self) -> Result<String, BodyError> {
+		self.get_request_mut().get_body().await
+	}
+	async fn get_body_bytes(&mut self) -> Result<&[u8], BodyError> {
+		self.get_request_mut().get_body_bytes().await
+	}
+	fn take_body_stream(&mut self) -> Result<Body, BodyError> {
+		self.get_request_mut().take_body_stream()
 	}
 	fn json<TBody>(&mut self, body: TBody) -> &mut Self
 	where
@@ -35,6 +59,147 @@ pub trait Context {
 		self.get_response_mut().set_body_bytes(bytes);
 		self
 	}
+	fn body_stream<TStream, TChunk, TError>(
+		&mut self,
+		stream: TStream,
+	) -> &mut Self
+	where
+		TStream: Stream<Item = Result<TChunk, TError>> + Send + 'static,
+		TChunk: Into<Bytes> + 'static,
+		TError: Into<Box<dyn StdError + Send + Sync>> + 'static,
+	{
+		self.get_response_mut().set_body_stream(stream);
+		self
+	}
+	fn body_reader<TReader>(&mut self, reader: TReader) -> &mut Self
+	where
+		TReader: AsyncRead + Send + 'static,
+	{
+		self.get_response_mut().set_body_reader(reader);
+		self
+	}
+	async fn body_file(&mut self, file: File) -> IoResult<&mut Self> {
+		self.get_response_mut().set_body_file(file).await?;
+		Ok(self)
+	}
+
+	/// Turns the response into a Server-Sent Events stream and returns the
+	/// sender for the events. If `keep_alive` is set, a comment is sent at
+	/// that interval so that proxies don't close an idle connection and so
+	/// that a client disconnect is noticed.
+	fn sse(&mut self, keep_alive: Option<Duration>) -> SseSender {
+		let (sender, stream) = sse::channel(keep_alive);
+		self.status(200)
+			.content_type("text/event-stream")
+			.header("Cache-Control", "no-cache")
+			.body_stream(stream);
+		sender
+	}
+	fn get_last_event_id(&self) -> Option<String> {
+		self.get_request().get_last_event_id()
+	}
+
+	fn get_pub_sub(&self) -> &PubSub {
+		self.get_request().get_pub_sub()
+	}
+
+	fn get_cancellation_token(&self) -> &CancellationToken {
+		self.get_request().get_cancellation_token()
+	}
+	fn is_cancelled(&self) -> bool {
+		self.get_request().is_cancelled()
+	}
+
+	fn get_route(&self) -> Option<&str> {
+		self.get_request().get_route()
+	}
+
+	fn get_request_id(&self) -> Option<&str> {
+		self.get_request().get_request_id()
+	}
+
+	fn get_extension<TValue>(&self) -> Option<&TValue>
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.get_request().get_extension()
+	}
+	fn get_extension_mut<TValue>(&mut self) -> Option<&mut TValue>
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.get_request_mut().get_extension_mut()
+	}
+	/// Stores a value on the request, replacing any value of the same type
+	fn extension<TValue>(&mut self, value: TValue) -> &mut Self
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.get_request_mut().insert_extension(value);
+		self
+	}
+	fn remove_extension<TValue>(&mut self) -> Option<TValue>
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.get_request_mut().remove_extension()
+	}
+
+	fn get_local(&self, key: &str) -> Option<&Value> {
+		self.get_response().get_locals().get(key)
+	}
+	/// Sets a value that templates rendered for this response can read
+	fn local<TValue>(&mut self, key: &str, value: TValue) -> &mut Self
+	where
+		TValue: Serialize,
+	{
+		self.get_response_mut().set_local(
+			key,
+			serde_json::to_value(value)
+				.expect("unable to serialize local into JSON"),
+		);
+		self
+	}
+
+	#[cfg(feature = "tracing")]
+	fn get_trace_context(&self) -> &TraceContext {
+		self.get_request().get_trace_context()
+	}
+
+	/// Accepts a CONNECT request or a request to upgrade the connection to
+	/// the protocol the client prefers, and returns a future that resolves to
+	/// the raw, bidirectional connection once the response has been sent.
+	/// The future should be awaited in a spawned task, since the response is
+	/// only sent once the middleware chain completes. Returns `None` if the
+	/// request doesn't ask for an upgrade.
+	fn accept_upgrade(&mut self) -> Option<OnUpgrade> {
+		if self.get_method() == &HttpMethod::Connect {
+			self.status(200);
+			return Some(self.get_request_mut().on_upgrade());
+		}
+		let protocol = self.get_request().get_upgrade_protocol()?;
+		self.accept_upgrade_to(&[&protocol])
+	}
+
+	/// Accepts a request to upgrade the connection to the first protocol the
+	/// client offered that's one of the given protocols. The response names
+	/// only the chosen protocol. Returns `None` if the client didn't offer
+	/// any of them.
+	fn accept_upgrade_to(&mut self, protocols: &[&str]) -> Option<OnUpgrade> {
+		let protocol = self
+			.get_request()
+			.get_upgrade_protocols()
+			.into_iter()
+			.find(|offered| {
+			protocols
+				.iter()
+				.any(|protocol| protocol.eq_ignore_ascii_case(offered))
+		})?;
+		self.status(101)
+			.header("Upgrade", &protocol)
+			.header("Connection", "Upgrade");
+		Some(self.get_request_mut().on_upgrade())
+	}
 
 	fn get_method(&self) -> &HttpMethod {
 		self.get_request().get_method()
@@ -61,6 +226,13 @@ pub trait Context {
 		self
 	}
 
+	/// Adds a timing to send in the `Server-Timing` header, such as the time
+	/// taken by a database query
+	fn timing(&mut self, name: &str, duration: Duration) -> &mut Self {
+		self.get_response_mut().add_timing(name, duration, None);
+		self
+	}
+
 	fn redirect(&mut self, destination: &str) -> &mut Self {
 		self.get_response_mut().redirect(destination);
 		self
@@ -87,6 +259,17 @@ pub trait Context {
 		self.get_request().get_query_string()
 	}
 
+	fn get_query(&self) -> &QueryMap {
+		self.get_request().get_query()
+	}
+
+	fn get_query_as<TQuery>(&self) -> Result<TQuery, QueryError>
+	where
+		TQuery: DeserializeOwned,
+	{
+		self.get_request().get_query_as()
+	}
+
 	fn get_host(&self) -> String {
 		self.get_request().get_host()
 	}
@@ -119,8 +302,25 @@ pub trait Context {
 		self.get_request().is(mimes)
 	}
 
-	// TODO content negotiation
-	// See: https://koajs.com/#request content negotiation
+	fn expects_continue(&self) -> bool {
+		self.get_request().expects_continue()
+	}
+
+	fn accepts(&self, types: &[&str]) -> Option<String> {
+		self.get_request().accepts(types)
+	}
+
+	fn accepts_encodings(&self, encodings: &[&str]) -> Option<String> {
+		self.get_request().accepts_encodings(encodings)
+	}
+
+	fn accepts_charsets(&self, charsets: &[&str]) -> Option<String> {
+		self.get_request().accepts_charsets(charsets)
+	}
+
+	fn accepts_languages(&self, languages: &[&str]) -> Option<String> {
+		self.get_request().accepts_languages(languages)
+	}
 
 	fn get_header(&self, key: &str) -> Option<String> {
 		self.get_request().get_header(key)
@@ -137,6 +337,13 @@ pub trait Context {
 		self.get_response_mut().remove_header(key);
 		self
 	}
+	fn get_typed_header<THeader: Header>(&self) -> Option<THeader> {
+		self.get_request().get_typed_header()
+	}
+	fn typed_header<THeader: Header>(&mut self, header: &THeader) -> &mut Self {
+		self.get_response_mut().set_typed_header(header);
+		self
+	}
 
 	fn get_cookie(&self, name: &str) -> Option<&Cookie> {
 		self.get_request().get_cookie(name)
@@ -24,6 +24,11 @@ impl CompressionHandler {
 	where
 		TContext: Context + Debug + Send + Sync,
 	{
+		// Streamed bodies aren't available to compress upfront
+		if context.get_response().is_streaming() {
+			return;
+		}
+
 		let allowed_encodings = context
 			.get_request()
 			.get_header("Accept-Encoding")
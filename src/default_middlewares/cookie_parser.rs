@@ -6,11 +6,11 @@ pub fn parser<TContext>(context: &mut TContext)
 where
 	TContext: Context + Debug + Send + Sync,
 {
-	let header = context.get_request().get_headers().get("Cookie");
-	if header.is_none() {
+	let header = context.get_request().get_headers().get_all("Cookie");
+	if header.is_empty() {
 		return;
 	}
-	let header = header.unwrap().clone();
+	let header = header.to_vec();
 
 	context
 		.get_request_mut()
@@ -107,6 +107,7 @@ where
 				}),
 			)
 			.replace(":remote-addr", &context.get_ip().to_string())
+			.replace(":request-id", context.get_request_id().unwrap_or("-"))
 			.replace(
 				":response-time",
 				&if elapsed_time.as_millis() > 0 {
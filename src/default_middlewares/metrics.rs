@@ -0,0 +1,402 @@
+use std::{
+	collections::BTreeMap,
+	fmt::{Debug, Write},
+	sync::{Arc, Mutex},
+	time::Instant,
+};
+
+use crate::{Context, Error, HttpMethod, Middleware, NextHandler};
+
+pub const DEFAULT_PATH: &str = "/metrics";
+pub const DEFAULT_DURATION_BUCKETS: &[f64] = &[
+	0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+pub const DEFAULT_SIZE_BUCKETS: &[f64] = &[
+	100.0,
+	1_000.0,
+	10_000.0,
+	100_000.0,
+	1_000_000.0,
+	10_000_000.0,
+];
+// Requests that don't match a route are grouped together, so that scanners
+// can't create a new series for every path they try
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RouteLabels {
+	method: String,
+	route: String,
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ResponseLabels {
+	method: String,
+	route: String,
+	status: String,
+}
+
+/// A histogram keeps the buckets it was created with, since clones of a
+/// handler share their metrics but can be given different buckets
+struct Histogram {
+	buckets: Arc<Vec<f64>>,
+	counts: Vec<u64>,
+	sum: f64,
+	count: u64,
+}
+
+impl Histogram {
+	fn new(buckets: &Arc<Vec<f64>>) -> Self {
+		Histogram {
+			buckets: buckets.clone(),
+			counts: vec![0; buckets.len()],
+			sum: 0.0,
+			count: 0,
+		}
+	}
+
+	fn observe(&mut self, value: f64) {
+		if let Some(index) =
+			self.buckets.iter().position(|bucket| value <= *bucket)
+		{
+			self.counts[index] += 1;
+		}
+		self.sum += value;
+		self.count += 1;
+	}
+}
+
+#[derive(Default)]
+struct Metrics {
+	requests: BTreeMap<ResponseLabels, u64>,
+	durations: BTreeMap<ResponseLabels, Histogram>,
+	sizes: BTreeMap<ResponseLabels, Histogram>,
+	in_flight: BTreeMap<RouteLabels, i64>,
+}
+
+/// Collects request metrics and serves them in the Prometheus text format.
+/// Clones share the same metrics, so the handler can be cloned into several
+/// apps.
+#[derive(Clone)]
+pub struct MetricsHandler {
+	path: String,
+	duration_buckets: Arc<Vec<f64>>,
+	size_buckets: Arc<Vec<f64>>,
+	metrics: Arc<Mutex<Metrics>>,
+}
+
+impl MetricsHandler {
+	pub fn create() -> MetricsHandler {
+		MetricsHandler {
+			path: DEFAULT_PATH.to_string(),
+			duration_buckets: Arc::new(DEFAULT_DURATION_BUCKETS.to_vec()),
+			size_buckets: Arc::new(DEFAULT_SIZE_BUCKETS.to_vec()),
+			metrics: Arc::new(Mutex::new(Metrics::default())),
+		}
+	}
+
+	/// Sets the path the metrics are served on, which is `/metrics` by
+	/// default
+	pub fn path(mut self, path: &str) -> Self {
+		self.path = path.to_string();
+		self
+	}
+
+	/// Sets the upper bounds, in seconds, of the buckets of the request
+	/// duration histogram
+	pub fn duration_buckets(mut self, buckets: &[f64]) -> Self {
+		self.duration_buckets = Arc::new(sorted_buckets(buckets));
+		self
+	}
+
+	/// Sets the upper bounds, in bytes, of the buckets of the response size
+	/// histogram
+	pub fn size_buckets(mut self, buckets: &[f64]) -> Self {
+		self.size_buckets = Arc::new(sorted_buckets(buckets));
+		self
+	}
+
+	pub async fn run<TContext, TErrorData>(
+		&self,
+		mut context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>>
+	where
+		TContext: Context + Debug + Send + Sync,
+		TErrorData: Default + Send + Sync,
+	{
+		if context.get_method() == &HttpMethod::Get &&
+			context.get_path() == self.path
+		{
+			context
+				.content_type("text/plain; version=0.0.4; charset=utf-8")
+				.body(&self.render());
+			return Ok(context);
+		}
+
+		let route_labels = RouteLabels {
+			method: context.get_method().to_string(),
+			route: context.get_route().unwrap_or(UNMATCHED_ROUTE).to_string(),
+		};
+		let in_flight = InFlightGuard::new(self, route_labels.clone());
+		let start = Instant::now();
+
+		let result = next(context).await;
+
+		let elapsed = start.elapsed().as_secs_f64();
+		let (status, size) = match &result {
+			Ok(context) => {
+				let response = context.get_response();
+				let size = if response.is_streaming() {
+					response
+						.get_header("Content-Length")
+						.and_then(|length| length.parse::<usize>().ok())
+				} else {
+					Some(response.get_body().len())
+				};
+				(response.get_status(), size)
+			}
+			Err(err) => (
+				err.get_status().unwrap_or(500),
+				err.get_body_bytes().map(|body| body.len()),
+			),
+		};
+		drop(in_flight);
+
+		let labels = ResponseLabels {
+			method: route_labels.method,
+			route: route_labels.route,
+			status: format!("{}xx", status / 100),
+		};
+		let mut metrics = self.metrics.lock().unwrap();
+		*metrics.requests.entry(labels.clone()).or_insert(0) += 1;
+		metrics
+			.durations
+			.entry(labels.clone())
+			.or_insert_with(|| Histogram::new(&self.duration_buckets))
+			.observe(elapsed);
+		// The size of streamed responses without a content length isn't known
+		if let Some(size) = size {
+			metrics
+				.sizes
+				.entry(labels)
+				.or_insert_with(|| Histogram::new(&self.size_buckets))
+				.observe(size as f64);
+		}
+		drop(metrics);
+
+		result
+	}
+
+	/// Renders the metrics collected so far in the Prometheus text format
+	pub fn render(&self) -> String {
+		let metrics = self.metrics.lock().unwrap();
+		let mut output = String::new();
+
+		write_header(
+			&mut output,
+			"http_requests_total",
+			"counter",
+			"The number of HTTP requests handled.",
+		);
+		for (labels, count) in &metrics.requests {
+			let _ = writeln!(
+				output,
+				"http_requests_total{{{}}} {}",
+				format_response_labels(labels),
+				count
+			);
+		}
+
+		write_header(
+			&mut output,
+			"http_requests_in_flight",
+			"gauge",
+			"The number of HTTP requests being handled.",
+		);
+		for (labels, count) in &metrics.in_flight {
+			let _ = writeln!(
+				output,
+				"http_requests_in_flight{{method=\"{}\",route=\"{}\"}} {}",
+				escape(&labels.method),
+				escape(&labels.route),
+				count
+			);
+		}
+
+		write_histogram(
+			&mut output,
+			"http_request_duration_seconds",
+			"The time taken to handle HTTP requests, in seconds.",
+			&metrics.durations,
+		);
+		write_histogram(
+			&mut output,
+			"http_response_size_bytes",
+			"The size of HTTP response bodies, in bytes.",
+			&metrics.sizes,
+		);
+
+		output
+	}
+}
+
+#[async_trait::async_trait]
+impl<TContext, TErrorData> Middleware<TContext, TErrorData> for MetricsHandler
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	async fn run_middleware(
+		&self,
+		context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>> {
+		self.run(context, next).await
+	}
+}
+
+/// Records metrics for the requests after it, and serves them on `/metrics`.
+/// Mount it before the other middlewares, so that it measures all of them.
+pub fn metrics() -> MetricsHandler {
+	MetricsHandler::create()
+}
+
+/// Counts a request as in flight until it's dropped, so that requests that
+/// are cancelled midway aren't counted forever
+struct InFlightGuard<'a> {
+	handler: &'a MetricsHandler,
+	labels: RouteLabels,
+}
+
+impl<'a> InFlightGuard<'a> {
+	fn new(handler: &'a MetricsHandler, labels: RouteLabels) -> Self {
+		*handler
+			.metrics
+			.lock()
+			.unwrap()
+			.in_flight
+			.entry(labels.clone())
+			.or_insert(0) += 1;
+		InFlightGuard { handler, labels }
+	}
+}
+
+impl Drop for InFlightGuard<'_> {
+	fn drop(&mut self) {
+		if let Ok(mut metrics) = self.handler.metrics.lock() {
+			if let Some(count) = metrics.in_flight.get_mut(&self.labels) {
+				*count -= 1;
+			}
+		}
+	}
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(output, "# HELP {} {}", name, help);
+	let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+fn write_histogram(
+	output: &mut String,
+	name: &str,
+	help: &str,
+	histograms: &BTreeMap<ResponseLabels, Histogram>,
+) {
+	write_header(output, name, "histogram", help);
+	for (labels, histogram) in histograms {
+		let labels = format_response_labels(labels);
+		// Buckets are cumulative in the exposition format
+		let mut cumulative = 0;
+		for (bucket, count) in histogram.buckets.iter().zip(&histogram.counts) {
+			cumulative += count;
+			let _ = writeln!(
+				output,
+				"{}_bucket{{{},le=\"{}\"}} {}",
+				name, labels, bucket, cumulative
+			);
+		}
+		let _ = writeln!(
+			output,
+			"{}_bucket{{{},le=\"+Inf\"}} {}",
+			name, labels, histogram.count
+		);
+		let _ =
+			writeln!(output, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+		let _ = writeln!(
+			output,
+			"{}_count{{{}}} {}",
+			name, labels, histogram.count
+		);
+	}
+}
+
+/// Sorts the upper bounds of histogram buckets and removes duplicates, as
+/// the exposition format requires the buckets to be cumulative. NaN isn't a
+/// valid bound, so it is dropped.
+fn sorted_buckets(buckets: &[f64]) -> Vec<f64> {
+	let mut buckets = buckets
+		.iter()
+		.copied()
+		.filter(|bucket| !bucket.is_nan())
+		.collect::<Vec<_>>();
+	buckets.sort_by(f64::total_cmp);
+	buckets.dedup();
+	buckets
+}
+
+fn format_response_labels(labels: &ResponseLabels) -> String {
+	format!(
+		"method=\"{}\",route=\"{}\",status=\"{}\"",
+		escape(&labels.method),
+		escape(&labels.route),
+		escape(&labels.status)
+	)
+}
+
+/// Escapes a label value for the exposition format
+fn escape(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn buckets_are_sorted_without_duplicates() {
+		let handler = metrics()
+			.duration_buckets(&[1.0, f64::NAN, 0.1, 1.0, 0.5])
+			.size_buckets(&[1000.0, 100.0, 100.0]);
+		assert_eq!(*handler.duration_buckets, vec![0.1, 0.5, 1.0]);
+		assert_eq!(*handler.size_buckets, vec![100.0, 1000.0]);
+	}
+
+	#[test]
+	fn histograms_keep_their_own_buckets() {
+		// Created by a handler with a single bucket, and observed by a clone
+		// of it with more buckets
+		let mut histogram = Histogram::new(&Arc::new(vec![1.0]));
+		histogram.observe(5.0);
+		histogram.observe(0.5);
+		assert_eq!(histogram.counts, vec![1]);
+		assert_eq!(histogram.count, 2);
+
+		let mut histograms = BTreeMap::new();
+		histograms.insert(
+			ResponseLabels {
+				method: "GET".to_string(),
+				route: "/".to_string(),
+				status: "2xx".to_string(),
+			},
+			histogram,
+		);
+		let mut output = String::new();
+		write_histogram(&mut output, "test", "A test histogram.", &histograms);
+		assert!(output.contains("le=\"1\"} 1\n"));
+		assert!(output.contains("le=\"+Inf\"} 2\n"));
+	}
+}
@@ -3,5 +3,9 @@ pub mod cookie_parser;
 mod helmet;
 pub mod json;
 pub mod logger;
+pub mod metrics;
+pub mod request_id;
+pub mod server_timing;
 pub mod static_file_server;
+pub mod timeout;
 pub mod url_encoded;
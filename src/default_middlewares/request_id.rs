@@ -0,0 +1,107 @@
+use std::fmt::Debug;
+
+use uuid::Uuid;
+
+use crate::{Context, Error, Middleware, NextHandler};
+
+pub const DEFAULT_HEADER: &str = "X-Request-Id";
+// Longer IDs are more likely to be junk than a real ID from another service
+const MAX_INCOMING_LENGTH: usize = 200;
+
+#[derive(Clone)]
+pub struct RequestIdHandler {
+	header: String,
+	trust_incoming: bool,
+	generator: fn() -> String,
+}
+
+impl RequestIdHandler {
+	pub fn create() -> RequestIdHandler {
+		RequestIdHandler {
+			header: DEFAULT_HEADER.to_string(),
+			trust_incoming: true,
+			generator: generate_uuid,
+		}
+	}
+
+	/// Sets the header the ID is read from and echoed on, which is
+	/// `X-Request-Id` by default
+	pub fn header(mut self, header: &str) -> Self {
+		self.header = header.to_string();
+		self
+	}
+
+	/// Sets whether an ID sent by the client is reused, which it is by
+	/// default. Disable this if the app isn't behind a proxy or service that
+	/// sets the ID.
+	pub fn trust_incoming(mut self, trust_incoming: bool) -> Self {
+		self.trust_incoming = trust_incoming;
+		self
+	}
+
+	/// Sets the function used to generate new IDs, such as one that generates
+	/// ULIDs. IDs are UUIDv4s by default.
+	pub fn generator(mut self, generator: fn() -> String) -> Self {
+		self.generator = generator;
+		self
+	}
+
+	pub async fn run<TContext, TErrorData>(
+		&self,
+		mut context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>>
+	where
+		TContext: Context + Debug + Send + Sync,
+		TErrorData: Default + Send + Sync,
+	{
+		let request_id = context
+			.get_header(&self.header)
+			.filter(|request_id| self.trust_incoming && is_valid(request_id))
+			.unwrap_or_else(self.generator);
+		context.get_request_mut().set_request_id(&request_id);
+
+		// Set once the chain is done, so that it isn't lost if a later
+		// middleware replaces the headers
+		match next(context).await {
+			Ok(mut context) => {
+				context.header(&self.header, &request_id);
+				Ok(context)
+			}
+			Err(err) => Err(err.header(&self.header, &request_id)),
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl<TContext, TErrorData> Middleware<TContext, TErrorData> for RequestIdHandler
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	async fn run_middleware(
+		&self,
+		context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>> {
+		self.run(context, next).await
+	}
+}
+
+/// Reuses the `X-Request-Id` of the request, or generates one if there isn't
+/// one. The ID is stored on the request and echoed on the response, including
+/// the responses for errors.
+pub fn request_id() -> RequestIdHandler {
+	RequestIdHandler::create()
+}
+
+fn generate_uuid() -> String {
+	Uuid::new_v4().to_string()
+}
+
+/// Only reuse IDs that can be safely logged and sent back as a header
+fn is_valid(request_id: &str) -> bool {
+	!request_id.is_empty() &&
+		request_id.len() <= MAX_INCOMING_LENGTH &&
+		request_id.bytes().all(|byte| byte.is_ascii_graphic())
+}
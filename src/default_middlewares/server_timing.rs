@@ -0,0 +1,101 @@
+use std::{fmt::Debug, time::Instant};
+
+use crate::{Context, Error, Middleware, NextHandler, ServerTiming};
+
+#[derive(Clone)]
+pub struct ServerTimingHandler {
+	total: bool,
+	middleware_timings: bool,
+}
+
+impl ServerTimingHandler {
+	pub fn create() -> ServerTimingHandler {
+		ServerTimingHandler {
+			total: true,
+			middleware_timings: false,
+		}
+	}
+
+	/// Sets whether a `total` timing for the rest of the chain is added,
+	/// which it is by default
+	pub fn total(mut self, total: bool) -> Self {
+		self.total = total;
+		self
+	}
+
+	/// Sets whether each middleware after this one gets a timing, named
+	/// after its position in the chain and described by the path it's
+	/// mounted on. The time a middleware spends waiting for `next` isn't
+	/// counted towards its own timing.
+	pub fn middleware_timings(mut self, middleware_timings: bool) -> Self {
+		self.middleware_timings = middleware_timings;
+		self
+	}
+
+	pub async fn run<TContext, TErrorData>(
+		&self,
+		mut context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>>
+	where
+		TContext: Context + Debug + Send + Sync,
+		TErrorData: Default + Send + Sync,
+	{
+		if self.middleware_timings {
+			context.get_request_mut().record_middleware_timings = true;
+		}
+		let start = Instant::now();
+
+		match next(context).await {
+			Ok(mut context) => {
+				if self.total {
+					context.timing("total", start.elapsed());
+				}
+				let header = context
+					.get_response()
+					.get_timings()
+					.iter()
+					.map(ServerTiming::to_string)
+					.collect::<Vec<_>>()
+					.join(", ");
+				if !header.is_empty() {
+					context.header("Server-Timing", &header);
+				}
+				Ok(context)
+			}
+			// The timings added to the response are dropped along with it, so
+			// only the total is sent for errors
+			Err(err) if self.total => {
+				let total = ServerTiming {
+					name: "total".to_string(),
+					duration: start.elapsed(),
+					description: None,
+				};
+				Err(err.header("Server-Timing", &total.to_string()))
+			}
+			Err(err) => Err(err),
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl<TContext, TErrorData> Middleware<TContext, TErrorData>
+	for ServerTimingHandler
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	async fn run_middleware(
+		&self,
+		context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>> {
+		self.run(context, next).await
+	}
+}
+
+/// Sends the timings added with `Context::timing` in the `Server-Timing`
+/// header, so that they show up in the browser's devtools
+pub fn server_timing() -> ServerTimingHandler {
+	ServerTimingHandler::create()
+}
@@ -0,0 +1,103 @@
+use std::{
+	fmt::Debug,
+	future::Future,
+	io::{Error as IoError, ErrorKind},
+	time::Duration,
+};
+
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+use crate::{Context, Error, Middleware, NextHandler};
+
+pub const DEFAULT_TIMEOUT_STATUS: u16 = 503;
+
+#[derive(Clone)]
+pub struct TimeoutHandler {
+	timeout: Duration,
+	status: u16,
+}
+
+impl TimeoutHandler {
+	pub fn create(timeout: Duration) -> TimeoutHandler {
+		TimeoutHandler {
+			timeout,
+			status: DEFAULT_TIMEOUT_STATUS,
+		}
+	}
+
+	/// Sets the status of the error returned when the timeout elapses, which
+	/// is 503 by default
+	pub fn status(mut self, status: u16) -> Self {
+		self.status = status;
+		self
+	}
+
+	pub async fn run<TContext, TErrorData>(
+		&self,
+		context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>>
+	where
+		TContext: Context + Debug + Send + Sync,
+		TErrorData: Default + Send + Sync,
+	{
+		let cancellation_token = context.get_cancellation_token().clone();
+		run_with_timeout(
+			self.timeout,
+			self.status,
+			cancellation_token,
+			next(context),
+		)
+		.await
+	}
+}
+
+#[async_trait::async_trait]
+impl<TContext, TErrorData> Middleware<TContext, TErrorData> for TimeoutHandler
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	async fn run_middleware(
+		&self,
+		context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>> {
+		self.run(context, next).await
+	}
+}
+
+/// Aborts the rest of the middleware chain if it doesn't complete within the
+/// timeout, returning an error with the given status instead
+pub fn timeout(timeout: Duration) -> TimeoutHandler {
+	TimeoutHandler::create(timeout)
+}
+
+/// Runs the middleware chain, dropping it if the timeout elapses. The error
+/// returned on a timeout wraps an `IoError` of the kind
+/// [`ErrorKind::TimedOut`], so that error handlers can tell it apart.
+pub(crate) async fn run_with_timeout<TContext, TErrorData, TFuture>(
+	timeout: Duration,
+	status: u16,
+	cancellation_token: CancellationToken,
+	future: TFuture,
+) -> Result<TContext, Error<TErrorData>>
+where
+	TErrorData: Default + Send + Sync,
+	TFuture: Future<Output = Result<TContext, Error<TErrorData>>>,
+{
+	match time::timeout(timeout, future).await {
+		Ok(result) => result,
+		Err(_) => {
+			// Let any work spawned for the request know that it's no longer
+			// needed
+			cancellation_token.cancel();
+			Err(Error::<TErrorData>::new(Box::new(IoError::from(
+				ErrorKind::TimedOut,
+			)))
+			.status(status)
+			.body("Request timed out"))
+		}
+	}
+}
@@ -4,18 +4,15 @@ use serde_json::Value;
 
 use crate::{AsError, Context, DefaultMiddleware, Error};
 
-pub fn parser<TContext, TErrorData>(
-	context: &TContext,
+pub async fn parser<TContext, TErrorData>(
+	context: &mut TContext,
 ) -> Result<Option<Value>, Error<TErrorData>>
 where
 	TContext: 'static + Context + Debug + Send + Sync,
 	TErrorData: Default + Send + Sync,
 {
 	if context.is(&["application/x-www-form-urlencoded"]) {
-		let body = context
-			.get_request()
-			.get_body()
-			.unwrap_or_else(|_| "None".to_string());
+		let body = context.get_body().await?;
 		Ok(Some(
 			serde_urlencoded::from_bytes(body.as_bytes())
 				.status(500)
@@ -32,7 +29,7 @@ where
 {
 	DefaultMiddleware::new(|mut context, next| {
 		Box::pin(async move {
-			let json = parser(&context)?;
+			let json = parser(&mut context).await?;
 
 			if let Some(json) = json {
 				context.set_body_object(json);
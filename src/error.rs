@@ -4,6 +4,8 @@ use std::{
 	ops::{Deref, DerefMut},
 };
 
+use crate::{headers::HeaderMap, BodyError, QueryError};
+
 #[derive(Debug)]
 pub struct Error<TErrorData>
 where
@@ -12,6 +14,7 @@ where
 	error: Box<dyn StdError + Send + Sync>,
 	status: Option<u16>,
 	body: Option<Vec<u8>>,
+	headers: HeaderMap,
 	data: TErrorData,
 }
 
@@ -23,6 +26,7 @@ where
 		Error {
 			body: None,
 			status: None,
+			headers: HeaderMap::new(),
 			error,
 			data: TErrorData::default(),
 		}
@@ -36,6 +40,7 @@ where
 			error,
 			body: None,
 			status: None,
+			headers: HeaderMap::new(),
 			data,
 		}
 	}
@@ -44,6 +49,7 @@ where
 		Error {
 			body: None,
 			status: None,
+			headers: HeaderMap::new(),
 			error: Box::new(IoError::from(ErrorKind::NotFound)),
 			data: TErrorData::default(),
 		}
@@ -79,7 +85,20 @@ where
 		self
 	}
 
-	pub fn get_error(&self) -> &(dyn StdError + Send + Sync) {
+	/// Returns the headers that are sent with the response for the error
+	pub fn get_headers(&self) -> &HeaderMap {
+		&self.headers
+	}
+
+	/// Sets a header on the response for the error, for headers that should
+	/// be sent even when a request fails, such as a request ID. The response
+	/// is built from these headers before it's handed to the error handler.
+	pub fn header(mut self, key: &str, value: &str) -> Self {
+		self.headers.set(key, value);
+		self
+	}
+
+	pub fn get_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
 		self.error.as_ref()
 	}
 
@@ -146,7 +165,32 @@ where
 	TErrorData: Default + Send + Sync,
 {
 	fn from(err: StdErr) -> Self {
-		Self::new_with_data(Box::new(err), Default::default())
+		Self::new_with_data(Box::new(err), Default::default()).known_error()
+	}
+}
+
+impl<TErrorData> Error<TErrorData>
+where
+	TErrorData: Default + Send + Sync,
+{
+	/// Sets the status of the errors that eve returns, such as a body that's
+	/// too large, even when they're the source of another error, like a hyper
+	/// error from reading a limited body stream. Errors caused by the client,
+	/// like an invalid query string, also get a body describing them.
+	fn known_error(self) -> Self {
+		let mut error = Some(self.get_error() as &(dyn StdError + 'static));
+		while let Some(current) = error {
+			if let Some(err) = current.downcast_ref::<BodyError>() {
+				let status = err.get_status();
+				return self.status(status);
+			}
+			if let Some(err) = current.downcast_ref::<QueryError>() {
+				let (status, body) = (err.get_status(), err.to_string());
+				return self.status(status).body(body);
+			}
+			error = current.source();
+		}
+		self
 	}
 }
 
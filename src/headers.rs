@@ -1,222 +1,1256 @@
-// TODO incomplete file
+use std::{
+	fmt::{Debug, Formatter, Result as FmtResult},
+	str::FromStr,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use percent_encoding::{
+	percent_decode_str,
+	utf8_percent_encode,
+	AsciiSet,
+	CONTROLS,
+};
 
 use crate::HttpMethod;
 
-pub struct RequestHeaders {
-	
-}
-
-pub struct Headers {
-	pub accept: String,
-	pub accept_charset: String,
-	pub accept_encoding: Encoding,
-	pub accept_language: String,
-	pub accept_patch: Vec<String>,
-	pub accept_post: Vec<String>,
-	pub accept_ranges: Vec<String>,
-	pub access_control_allow_credentials: bool,
-	pub access_control_allow_headers: Vec<String>,
-	pub access_control_allow_methods: Vec<HttpMethod>,
-	pub access_control_allow_origin: Vec<String>,
-	pub access_control_expose_headers: Vec<String>,
-	pub access_control_max_age: u64,
-	pub access_control_request_headers: Vec<String>,
-	pub access_control_request_method: Vec<HttpMethod>,
-	pub age: u64,
-	pub allow: Vec<HttpMethod>,
-	pub alt_svc: Vec<String>,
-	pub authorization: String,
-	pub cache_control: String,
-	pub clear_site_data: Vec<String>,
-	pub connection: ConnectionDirective,
-	pub content_disposition: ContentDisposition,
-	pub content_encoding: ContentEncoding,
-	pub content_language: Vec<String>,
-	pub content_length: u64,
-	pub content_location: String,
-	pub content_range: String,
-	pub content_security_policy: String,
-	pub content_Security-Policy-Report-Only
-	pub content_Type
-	Cookie
-	Cross-Origin-Embedder-Policy
-	Cross-Origin-Opener-Policy
-	Cross-Origin-Resource-Policy
-	DNT
-	DPR
-	Date
-	Device-Memory
-	Digest
-	ETag
-	Early-Data
-	Expect
-	Expect-CT
-	Expires
-	Feature-Policy
-	Forwarded
-	From
-	Host
-	If-Match
-	If-Modified-Since
-	If-None-Match
-	If-Range
-	If-Unmodified-Since
-	Index
-	Keep-Alive
-	Large-Allocation
-	Last-Modified
-	Link
-	Location
-	NEL
-	Origin
-	Pragma
-	Proxy-Authenticate
-	Proxy-Authorization
-	Public-Key-Pins
-	Public-Key-Pins-Report-Only
-	Range
-	Referer
-	Referrer-Policy
-	Retry-After
-	Save-Data
-	Sec-Fetch-Dest
-	Sec-Fetch-Mode
-	Sec-Fetch-Site
-	Sec-Fetch-User
-	Sec-WebSocket-Accept
-	Server
-	Server-Timing
-	Set-Cookie
-	SourceMap
-	Strict-Transport-Security
-	TE
-	Timing-Allow-Origin
-	Tk
-	Trailer
-	Transfer-Encoding
-	Upgrade
-	Upgrade-Insecure-Requests
-	User-Agent
-	Vary
-	Via
-	WWW-Authenticate
-	Want-Digest
-	Warning
-	X-Content-Type-Options
-	X-DNS-Prefetch-Control
-	X-Forwarded-For
-	X-Forwarded-Host
-	X-Forwarded-Proto
-	X-Frame-Options
-	X-XSS-Protection
-}
-
-pub enum Encoding {
-	Gzip,
-	Compress,
-	Deflate,
-	Identity,
-	Brotli,
-	Any
-}
-
-pub enum ContentDisposition {
-	Inline,
-	Attachment {
-		file_name: String
+// Characters that have to be percent-encoded in an RFC 8187 extended value
+const EXTENDED_VALUE: &AsciiSet = &CONTROLS
+	.add(b' ')
+	.add(b'"')
+	.add(b'%')
+	.add(b'\'')
+	.add(b'(')
+	.add(b')')
+	.add(b'*')
+	.add(b',')
+	.add(b'/')
+	.add(b':')
+	.add(b';')
+	.add(b'<')
+	.add(b'=')
+	.add(b'>')
+	.add(b'?')
+	.add(b'@')
+	.add(b'[')
+	.add(b'\\')
+	.add(b']')
+	.add(b'{')
+	.add(b'}');
+
+/// A header with a typed value, which can be read and written using
+/// `get_typed_header` and `set_typed_header` on requests and responses
+pub trait Header: Sized {
+	/// The name of the header, such as `Cache-Control`
+	const NAME: &'static str;
+
+	/// Parses the value of the header, returning `None` if it's invalid
+	fn parse(value: &str) -> Option<Self>;
+
+	fn format(&self) -> String;
+}
+
+/// Headers with HTTP semantics. Names are matched case-insensitively, and
+/// both the order of the headers and the order of their values are kept.
+/// A header keeps the casing of the name it was first added with.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+	entries: Vec<(String, Vec<String>)>,
+}
+
+impl HeaderMap {
+	pub fn new() -> Self {
+		HeaderMap::default()
+	}
+
+	/// Returns the values of a header joined with commas, which is how
+	/// repeated headers are combined. `Set-Cookie` can't be combined this
+	/// way, so use [`get_all`](Self::get_all) for it instead.
+	pub fn get(&self, name: &str) -> Option<String> {
+		self.get_values(name).map(|values| values.join(", "))
+	}
+
+	/// Returns every value of a header, in the order they were added
+	pub fn get_all(&self, name: &str) -> &[String] {
+		self.get_values(name).unwrap_or_default()
+	}
+
+	pub fn contains(&self, name: &str) -> bool {
+		self.position(name).is_some()
+	}
+
+	/// Sets the value of a header, replacing any values it already had
+	pub fn set(&mut self, name: &str, value: &str) {
+		match self.position(name) {
+			Some(index) => self.entries[index].1 = vec![value.to_string()],
+			None => self
+				.entries
+				.push((name.to_string(), vec![value.to_string()])),
+		}
+	}
+
+	/// Adds a value to a header, keeping any values it already had
+	pub fn append(&mut self, name: &str, value: &str) {
+		match self.position(name) {
+			Some(index) => self.entries[index].1.push(value.to_string()),
+			None => self
+				.entries
+				.push((name.to_string(), vec![value.to_string()])),
+		}
+	}
+
+	/// Removes a header, returning its values if it was set
+	pub fn remove(&mut self, name: &str) -> Option<Vec<String>> {
+		self.position(name)
+			.map(|index| self.entries.remove(index).1)
+	}
+
+	/// Keeps only the headers for which the predicate returns true
+	pub fn retain<TPredicate>(&mut self, mut predicate: TPredicate)
+	where
+		TPredicate: FnMut(&str, &[String]) -> bool,
+	{
+		self.entries
+			.retain(|(name, values)| predicate(name, values));
+	}
+
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
+
+	/// Iterates over the headers and their values, in the order they were
+	/// added
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+		self.entries
+			.iter()
+			.map(|(name, values)| (name.as_str(), values.as_slice()))
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	fn position(&self, name: &str) -> Option<usize> {
+		self.entries
+			.iter()
+			.position(|(key, _)| key.eq_ignore_ascii_case(name))
+	}
+
+	fn get_values(&self, name: &str) -> Option<&[String]> {
+		self.position(name)
+			.map(|index| self.entries[index].1.as_slice())
+	}
+}
+
+impl Debug for HeaderMap {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+/// A value of an `Accept*` header along with its quality, such as
+/// `text/html;q=0.8`
+#[derive(Clone, Debug, PartialEq)]
+pub struct QualityValue {
+	pub value: String,
+	pub quality: f32,
+}
+
+impl QualityValue {
+	pub fn new(value: &str, quality: f32) -> Self {
+		QualityValue {
+			value: value.to_string(),
+			quality,
+		}
+	}
+}
+
+macro_rules! quality_list_header {
+	($(#[$meta:meta])* $name:ident, $header:expr) => {
+		$(#[$meta])*
+		#[derive(Clone, Debug, PartialEq)]
+		pub struct $name(pub Vec<QualityValue>);
+
+		impl Header for $name {
+			const NAME: &'static str = $header;
+
+			fn parse(value: &str) -> Option<Self> {
+				Some($name(parse_quality_list(value)))
+			}
+
+			fn format(&self) -> String {
+				format_quality_list(&self.0)
+			}
+		}
+	};
+}
+
+quality_list_header!(
+	/// The media types the client accepts, such as `text/html`
+	Accept,
+	"Accept"
+);
+quality_list_header!(
+	/// The charsets the client accepts, such as `utf-8`
+	AcceptCharset,
+	"Accept-Charset"
+);
+quality_list_header!(
+	/// The content encodings the client accepts, such as `gzip`
+	AcceptEncoding,
+	"Accept-Encoding"
+);
+quality_list_header!(
+	/// The languages the client prefers, such as `en-US`
+	AcceptLanguage,
+	"Accept-Language"
+);
+
+macro_rules! date_header {
+	($(#[$meta:meta])* $name:ident, $header:expr) => {
+		$(#[$meta])*
+		#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+		pub struct $name(pub DateTime<Utc>);
+
+		impl Header for $name {
+			const NAME: &'static str = $header;
+
+			fn parse(value: &str) -> Option<Self> {
+				parse_http_date(value).map($name)
+			}
+
+			fn format(&self) -> String {
+				format_http_date(&self.0)
+			}
+		}
+	};
+}
+
+date_header!(Date, "Date");
+date_header!(Expires, "Expires");
+date_header!(IfModifiedSince, "If-Modified-Since");
+date_header!(IfUnmodifiedSince, "If-Unmodified-Since");
+date_header!(LastModified, "Last-Modified");
+
+/// The methods a resource supports
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Allow(pub Vec<HttpMethod>);
+
+impl Header for Allow {
+	const NAME: &'static str = "Allow";
+
+	fn parse(value: &str) -> Option<Self> {
+		split_list(value)
+			.map(HttpMethod::from_str)
+			.collect::<Result<Vec<_>, _>>()
+			.ok()
+			.map(Allow)
+	}
+
+	fn format(&self) -> String {
+		self.0
+			.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<_>>()
+			.join(", ")
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Authorization {
+	Basic {
+		username: String,
+		password: String,
+	},
+	Bearer(String),
+	/// Any other scheme, with its credentials left as they are
+	Other {
+		scheme: String,
+		credentials: String,
 	},
-	FormData {
-		name: String,
-		file_name: Option<String>
-	}
-}
-
-pub enum ConnectionDirective {
-	Close,
-	Headers(Vec<String>)
-}
-
-pub struct ContentRangeDirective {
-	pub unit: ContentRangeUnit,
-	pub range_start: u64,
-	pub range_end: u64,
-	pub size: u64,
-}
-
-(AcceptEncoding, ACCEPT_ENCODING, "accept-encoding");
-(AcceptLanguage, ACCEPT_LANGUAGE, "accept-language");
-(AcceptRanges, ACCEPT_RANGES, "accept-ranges");
-(AccessControlAllowCredentials, ACCESS_CONTROL_ALLOW_CREDENTIALS, "access-control-allow-credentials");
-(AccessControlAllowHeaders, ACCESS_CONTROL_ALLOW_HEADERS, "access-control-allow-headers");
-(AccessControlAllowMethods, ACCESS_CONTROL_ALLOW_METHODS, "access-control-allow-methods");
-(AccessControlAllowOrigin, ACCESS_CONTROL_ALLOW_ORIGIN, "access-control-allow-origin");
-(AccessControlExposeHeaders, ACCESS_CONTROL_EXPOSE_HEADERS, "access-control-expose-headers");
-(AccessControlMaxAge, ACCESS_CONTROL_MAX_AGE, "access-control-max-age");
-(AccessControlRequestHeaders, ACCESS_CONTROL_REQUEST_HEADERS, "access-control-request-headers");
-(AccessControlRequestMethod, ACCESS_CONTROL_REQUEST_METHOD, "access-control-request-method");
-(Age, AGE, "age");
-(Allow, ALLOW, "allow");
-(AltSvc, ALT_SVC, "alt-svc");
-(Authorization, AUTHORIZATION, "authorization");
-(CacheControl, CACHE_CONTROL, "cache-control");
-(Connection, CONNECTION, "connection");
-(ContentDisposition, CONTENT_DISPOSITION, "content-disposition");
-(ContentEncoding, CONTENT_ENCODING, "content-encoding");
-(ContentLanguage, CONTENT_LANGUAGE, "content-language");
-(ContentLength, CONTENT_LENGTH, "content-length");
-(ContentLocation, CONTENT_LOCATION, "content-location");
-(ContentRange, CONTENT_RANGE, "content-range");
-(ContentSecurityPolicy, CONTENT_SECURITY_POLICY, "content-security-policy");
-(ContentSecurityPolicyReportOnly, CONTENT_SECURITY_POLICY_REPORT_ONLY, "content-security-policy-report-only");
-(ContentType, CONTENT_TYPE, "content-type");
-(Cookie, COOKIE, "cookie");
-(Dnt, DNT, "dnt");
-(Date, DATE, "date");
-(Etag, ETAG, "etag");
-(Expect, EXPECT, "expect");
-(Expires, EXPIRES, "expires");
-(Forwarded, FORWARDED, "forwarded");
-(From, FROM, "from");
-(Host, HOST, "host");
-(IfMatch, IF_MATCH, "if-match");
-(IfModifiedSince, IF_MODIFIED_SINCE, "if-modified-since");
-(IfNoneMatch, IF_NONE_MATCH, "if-none-match");
-(IfRange, IF_RANGE, "if-range");
-(IfUnmodifiedSince, IF_UNMODIFIED_SINCE, "if-unmodified-since");
-(LastModified, LAST_MODIFIED, "last-modified");
-(Link, LINK, "link");
-(Location, LOCATION, "location");
-(MaxForwards, MAX_FORWARDS, "max-forwards");
-(Origin, ORIGIN, "origin");
-(Pragma, PRAGMA, "pragma");
-(ProxyAuthenticate, PROXY_AUTHENTICATE, "proxy-authenticate");
-(ProxyAuthorization, PROXY_AUTHORIZATION, "proxy-authorization");
-(PublicKeyPins, PUBLIC_KEY_PINS, "public-key-pins");
-(PublicKeyPinsReportOnly, PUBLIC_KEY_PINS_REPORT_ONLY, "public-key-pins-report-only");
-(Range, RANGE, "range");
-(Referer, REFERER, "referer");
-(ReferrerPolicy, REFERRER_POLICY, "referrer-policy");
-(Refresh, REFRESH, "refresh");
-(RetryAfter, RETRY_AFTER, "retry-after");
-(SecWebSocketAccept, SEC_WEBSOCKET_ACCEPT, "sec-websocket-accept");
-(SecWebSocketExtensions, SEC_WEBSOCKET_EXTENSIONS, "sec-websocket-extensions");
-(SecWebSocketKey, SEC_WEBSOCKET_KEY, "sec-websocket-key");
-(SecWebSocketProtocol, SEC_WEBSOCKET_PROTOCOL, "sec-websocket-protocol");
-(SecWebSocketVersion, SEC_WEBSOCKET_VERSION, "sec-websocket-version");
-(Server, SERVER, "server");
-(SetCookie, SET_COOKIE, "set-cookie");
-(StrictTransportSecurity, STRICT_TRANSPORT_SECURITY, "strict-transport-security");
-(Te, TE, "te");
-(Trailer, TRAILER, "trailer");
-(TransferEncoding, TRANSFER_ENCODING, "transfer-encoding");
-(UserAgent, USER_AGENT, "user-agent");
-(Upgrade, UPGRADE, "upgrade");
-(UpgradeInsecureRequests, UPGRADE_INSECURE_REQUESTS, "upgrade-insecure-requests");
-(Vary, VARY, "vary");
-(Via, VIA, "via");
-(Warning, WARNING, "warning");
-(WwwAuthenticate, WWW_AUTHENTICATE, "www-authenticate");
-(XContentTypeOptions, X_CONTENT_TYPE_OPTIONS, "x-content-type-options");
-(XDnsPrefetchControl, X_DNS_PREFETCH_CONTROL, "x-dns-prefetch-control");
-(XFrameOptions, X_FRAME_OPTIONS, "x-frame-options");
-(XXssProtection, X_XSS_PROTECTION, "x-xss-protection");
\ No newline at end of file
+}
+
+impl Header for Authorization {
+	const NAME: &'static str = "Authorization";
+
+	fn parse(value: &str) -> Option<Self> {
+		let (scheme, credentials) = value.trim().split_once(' ')?;
+		let credentials = credentials.trim();
+		if scheme.eq_ignore_ascii_case("basic") {
+			let decoded =
+				String::from_utf8(BASE64.decode(credentials).ok()?).ok()?;
+			let (username, password) = decoded.split_once(':')?;
+			Some(Authorization::Basic {
+				username: username.to_string(),
+				password: password.to_string(),
+			})
+		} else if scheme.eq_ignore_ascii_case("bearer") {
+			Some(Authorization::Bearer(credentials.to_string()))
+		} else {
+			Some(Authorization::Other {
+				scheme: scheme.to_string(),
+				credentials: credentials.to_string(),
+			})
+		}
+	}
+
+	fn format(&self) -> String {
+		match self {
+			Authorization::Basic { username, password } => format!(
+				"Basic {}",
+				BASE64.encode(format!("{}:{}", username, password))
+			),
+			Authorization::Bearer(token) => format!("Bearer {}", token),
+			Authorization::Other {
+				scheme,
+				credentials,
+			} => format!("{} {}", scheme, credentials),
+		}
+	}
+}
+
+/// The directives of a `Cache-Control` header. Directives that aren't
+/// known are kept in `extensions`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheControl {
+	pub no_cache: bool,
+	pub no_store: bool,
+	pub no_transform: bool,
+	pub must_revalidate: bool,
+	pub proxy_revalidate: bool,
+	pub public: bool,
+	pub private: bool,
+	pub immutable: bool,
+	pub only_if_cached: bool,
+	pub max_age: Option<u64>,
+	pub s_max_age: Option<u64>,
+	pub max_stale: Option<u64>,
+	pub min_fresh: Option<u64>,
+	pub stale_while_revalidate: Option<u64>,
+	pub stale_if_error: Option<u64>,
+	pub extensions: Vec<(String, Option<String>)>,
+}
+
+impl Header for CacheControl {
+	const NAME: &'static str = "Cache-Control";
+
+	fn parse(value: &str) -> Option<Self> {
+		let mut cache_control = CacheControl::default();
+		for directive in split_list(value) {
+			let (name, argument) = match directive.split_once('=') {
+				Some((name, argument)) => {
+					(name.trim(), Some(unquote(argument.trim())))
+				}
+				None => (directive, None),
+			};
+			let seconds = argument
+				.as_deref()
+				.and_then(|argument| argument.parse().ok());
+			match name.to_lowercase().as_str() {
+				"no-cache" => cache_control.no_cache = true,
+				"no-store" => cache_control.no_store = true,
+				"no-transform" => cache_control.no_transform = true,
+				"must-revalidate" => cache_control.must_revalidate = true,
+				"proxy-revalidate" => cache_control.proxy_revalidate = true,
+				"public" => cache_control.public = true,
+				"private" => cache_control.private = true,
+				"immutable" => cache_control.immutable = true,
+				"only-if-cached" => cache_control.only_if_cached = true,
+				"max-age" => cache_control.max_age = seconds,
+				"s-maxage" => cache_control.s_max_age = seconds,
+				// max-stale without a value accepts any stale response
+				"max-stale" => {
+					cache_control.max_stale = seconds.or(Some(u64::MAX))
+				}
+				"min-fresh" => cache_control.min_fresh = seconds,
+				"stale-while-revalidate" => {
+					cache_control.stale_while_revalidate = seconds
+				}
+				"stale-if-error" => cache_control.stale_if_error = seconds,
+				_ => {
+					cache_control.extensions.push((name.to_string(), argument))
+				}
+			}
+		}
+		Some(cache_control)
+	}
+
+	fn format(&self) -> String {
+		let flags = [
+			(self.no_cache, "no-cache"),
+			(self.no_store, "no-store"),
+			(self.no_transform, "no-transform"),
+			(self.must_revalidate, "must-revalidate"),
+			(self.proxy_revalidate, "proxy-revalidate"),
+			(self.public, "public"),
+			(self.private, "private"),
+			(self.immutable, "immutable"),
+			(self.only_if_cached, "only-if-cached"),
+		];
+		let durations = [
+			(self.max_age, "max-age"),
+			(self.s_max_age, "s-maxage"),
+			(self.max_stale, "max-stale"),
+			(self.min_fresh, "min-fresh"),
+			(self.stale_while_revalidate, "stale-while-revalidate"),
+			(self.stale_if_error, "stale-if-error"),
+		];
+
+		let mut directives = flags
+			.iter()
+			.filter(|(enabled, _)| *enabled)
+			.map(|(_, name)| name.to_string())
+			.collect::<Vec<_>>();
+		directives.extend(durations.iter().filter_map(|(seconds, name)| {
+			match seconds {
+				Some(u64::MAX) if *name == "max-stale" => {
+					Some(name.to_string())
+				}
+				Some(seconds) => Some(format!("{}={}", name, seconds)),
+				None => None,
+			}
+		}));
+		directives.extend(self.extensions.iter().map(|(name, argument)| {
+			match argument {
+				Some(argument) => {
+					format!("{}={}", name, quote_if_needed(argument))
+				}
+				None => name.clone(),
+			}
+		}));
+		directives.join(", ")
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DispositionType {
+	Inline,
+	Attachment,
+	FormData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentDisposition {
+	pub disposition: DispositionType,
+	/// The name of the field, for `form-data`
+	pub name: Option<String>,
+	pub file_name: Option<String>,
+}
+
+impl ContentDisposition {
+	pub fn inline() -> Self {
+		ContentDisposition {
+			disposition: DispositionType::Inline,
+			name: None,
+			file_name: None,
+		}
+	}
+
+	pub fn attachment(file_name: Option<&str>) -> Self {
+		ContentDisposition {
+			disposition: DispositionType::Attachment,
+			name: None,
+			file_name: file_name.map(str::to_string),
+		}
+	}
+
+	pub fn form_data(name: &str, file_name: Option<&str>) -> Self {
+		ContentDisposition {
+			disposition: DispositionType::FormData,
+			name: Some(name.to_string()),
+			file_name: file_name.map(str::to_string),
+		}
+	}
+}
+
+impl Header for ContentDisposition {
+	const NAME: &'static str = "Content-Disposition";
+
+	fn parse(value: &str) -> Option<Self> {
+		let mut params = split_params(value).into_iter();
+		let (disposition, _) = params.next()?;
+		let disposition = match disposition.to_lowercase().as_str() {
+			"inline" => DispositionType::Inline,
+			"form-data" => DispositionType::FormData,
+			// Unknown types have to be treated as attachments
+			_ => DispositionType::Attachment,
+		};
+
+		let mut content_disposition = ContentDisposition {
+			disposition,
+			name: None,
+			file_name: None,
+		};
+		let mut extended_file_name = None;
+		for (key, value) in params {
+			match (key.to_lowercase().as_str(), value) {
+				("name", Some(value)) => content_disposition.name = Some(value),
+				("filename", Some(value)) => {
+					content_disposition.file_name = Some(value)
+				}
+				("filename*", Some(value)) => {
+					extended_file_name = decode_extended_value(&value)
+				}
+				_ => (),
+			}
+		}
+		// The extended file name takes precedence, since it can hold any
+		// character
+		if extended_file_name.is_some() {
+			content_disposition.file_name = extended_file_name;
+		}
+		Some(content_disposition)
+	}
+
+	fn format(&self) -> String {
+		let mut value = match self.disposition {
+			DispositionType::Inline => "inline",
+			DispositionType::Attachment => "attachment",
+			DispositionType::FormData => "form-data",
+		}
+		.to_string();
+		if let Some(name) = &self.name {
+			value.push_str(&format!("; name={}", quote(name)));
+		}
+		if let Some(file_name) = &self.file_name {
+			if file_name.is_ascii() {
+				value.push_str(&format!("; filename={}", quote(file_name)));
+			} else {
+				// Older clients only understand the plain file name, so give
+				// them an ASCII version of it
+				let fallback = file_name
+					.chars()
+					.map(|c| if c.is_ascii() { c } else { '_' })
+					.collect::<String>();
+				value.push_str(&format!(
+					"; filename={}; filename*=UTF-8''{}",
+					quote(&fallback),
+					utf8_percent_encode(file_name, EXTENDED_VALUE)
+				));
+			}
+		}
+		value
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentLength(pub u64);
+
+impl Header for ContentLength {
+	const NAME: &'static str = "Content-Length";
+
+	fn parse(value: &str) -> Option<Self> {
+		value.trim().parse().ok().map(ContentLength)
+	}
+
+	fn format(&self) -> String {
+		self.0.to_string()
+	}
+}
+
+/// The byte range of a partial response, such as `bytes 0-499/1234`. The
+/// range is `None` when responding to an unsatisfiable range request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentRange {
+	pub range: Option<(u64, u64)>,
+	pub complete_length: Option<u64>,
+}
+
+impl Header for ContentRange {
+	const NAME: &'static str = "Content-Range";
+
+	fn parse(value: &str) -> Option<Self> {
+		let (unit, value) = value.trim().split_once(' ')?;
+		if !unit.eq_ignore_ascii_case("bytes") {
+			return None;
+		}
+		let (range, complete_length) = value.split_once('/')?;
+		let range = match range {
+			"*" => None,
+			range => {
+				let (start, end) = range.split_once('-')?;
+				let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+				if start > end {
+					return None;
+				}
+				Some((start, end))
+			}
+		};
+		let complete_length = match complete_length {
+			"*" => None,
+			length => Some(length.parse().ok()?),
+		};
+		Some(ContentRange {
+			range,
+			complete_length,
+		})
+	}
+
+	fn format(&self) -> String {
+		let range = self
+			.range
+			.map(|(start, end)| format!("{}-{}", start, end))
+			.unwrap_or_else(|| "*".to_string());
+		let complete_length = self
+			.complete_length
+			.map(|length| length.to_string())
+			.unwrap_or_else(|| "*".to_string());
+		format!("bytes {}/{}", range, complete_length)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentType {
+	/// The media type, such as `text/html`
+	pub media_type: String,
+	pub params: Vec<(String, String)>,
+}
+
+impl ContentType {
+	pub fn new(media_type: &str) -> Self {
+		ContentType {
+			media_type: media_type.to_string(),
+			params: vec![],
+		}
+	}
+
+	pub fn get_param(&self, name: &str) -> Option<&str> {
+		self.params
+			.iter()
+			.find(|(key, _)| key.eq_ignore_ascii_case(name))
+			.map(|(_, value)| value.as_str())
+	}
+
+	pub fn get_charset(&self) -> Option<&str> {
+		self.get_param("charset")
+	}
+}
+
+impl Header for ContentType {
+	const NAME: &'static str = "Content-Type";
+
+	fn parse(value: &str) -> Option<Self> {
+		let mut params = split_params(value).into_iter();
+		let (media_type, _) = params.next()?;
+		if !media_type.contains('/') {
+			return None;
+		}
+		Some(ContentType {
+			media_type: media_type.to_lowercase(),
+			params: params
+				.filter_map(|(key, value)| Some((key.to_lowercase(), value?)))
+				.collect(),
+		})
+	}
+
+	fn format(&self) -> String {
+		let mut value = self.media_type.clone();
+		for (key, param) in &self.params {
+			value.push_str(&format!("; {}={}", key, quote_if_needed(param)));
+		}
+		value
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ETag {
+	pub tag: String,
+	pub weak: bool,
+}
+
+impl ETag {
+	pub fn strong(tag: &str) -> Self {
+		ETag {
+			tag: tag.to_string(),
+			weak: false,
+		}
+	}
+
+	pub fn weak(tag: &str) -> Self {
+		ETag {
+			tag: tag.to_string(),
+			weak: true,
+		}
+	}
+
+	/// Compares the tags, requiring both of them to be strong. Used for
+	/// `If-Match`.
+	pub fn strong_eq(&self, other: &ETag) -> bool {
+		!self.weak && !other.weak && self.tag == other.tag
+	}
+
+	/// Compares the tags, ignoring whether they're weak. Used for
+	/// `If-None-Match`.
+	pub fn weak_eq(&self, other: &ETag) -> bool {
+		self.tag == other.tag
+	}
+}
+
+impl Header for ETag {
+	const NAME: &'static str = "ETag";
+
+	fn parse(value: &str) -> Option<Self> {
+		match parse_etags(value)?.as_slice() {
+			[etag] => Some(etag.clone()),
+			_ => None,
+		}
+	}
+
+	fn format(&self) -> String {
+		format!("{}\"{}\"", if self.weak { "W/" } else { "" }, self.tag)
+	}
+}
+
+macro_rules! etag_condition_header {
+	($(#[$meta:meta])* $name:ident, $header:expr, $compare:ident) => {
+		$(#[$meta])*
+		#[derive(Clone, Debug, PartialEq, Eq)]
+		pub enum $name {
+			/// `*`, which matches any current representation
+			Any,
+			Tags(Vec<ETag>),
+		}
+
+		impl $name {
+			pub fn matches(&self, etag: &ETag) -> bool {
+				match self {
+					$name::Any => true,
+					$name::Tags(tags) => tags.iter().any(|tag| tag.$compare(etag)),
+				}
+			}
+		}
+
+		impl Header for $name {
+			const NAME: &'static str = $header;
+
+			fn parse(value: &str) -> Option<Self> {
+				if value.trim() == "*" {
+					Some($name::Any)
+				} else {
+					parse_etags(value).map($name::Tags)
+				}
+			}
+
+			fn format(&self) -> String {
+				match self {
+					$name::Any => "*".to_string(),
+					$name::Tags(tags) => tags
+						.iter()
+						.map(ETag::format)
+						.collect::<Vec<_>>()
+						.join(", "),
+				}
+			}
+		}
+	};
+}
+
+etag_condition_header!(
+	/// Makes a request conditional on the resource matching one of the tags,
+	/// using the strong comparison
+	IfMatch,
+	"If-Match",
+	strong_eq
+);
+etag_condition_header!(
+	/// Makes a request conditional on the resource matching none of the
+	/// tags, using the weak comparison
+	IfNoneMatch,
+	"If-None-Match",
+	weak_eq
+);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+	/// `start-end`, where both ends are inclusive
+	FromTo(u64, u64),
+	/// `start-`, up to the end of the resource
+	From(u64),
+	/// `-length`, the last bytes of the resource
+	Last(u64),
+}
+
+impl ByteRange {
+	/// Returns the inclusive start and end of the range within a resource of
+	/// the given length, or `None` if the range can't be satisfied
+	pub fn resolve(&self, length: u64) -> Option<(u64, u64)> {
+		if length == 0 {
+			return None;
+		}
+		match *self {
+			ByteRange::FromTo(start, end) if start < length => {
+				Some((start, end.min(length - 1)))
+			}
+			ByteRange::From(start) if start < length => {
+				Some((start, length - 1))
+			}
+			ByteRange::Last(suffix) if suffix > 0 => {
+				Some((length.saturating_sub(suffix), length - 1))
+			}
+			_ => None,
+		}
+	}
+}
+
+/// The byte ranges a client asks for, such as `bytes=0-499, 1000-`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Range(pub Vec<ByteRange>);
+
+impl Header for Range {
+	const NAME: &'static str = "Range";
+
+	fn parse(value: &str) -> Option<Self> {
+		let (unit, ranges) = value.trim().split_once('=')?;
+		if !unit.trim().eq_ignore_ascii_case("bytes") {
+			return None;
+		}
+		let ranges = split_list(ranges)
+			.map(|range| {
+				let (start, end) = range.split_once('-')?;
+				match (start.trim(), end.trim()) {
+					("", suffix) => Some(ByteRange::Last(suffix.parse().ok()?)),
+					(start, "") => Some(ByteRange::From(start.parse().ok()?)),
+					(start, end) => {
+						let (start, end) =
+							(start.parse().ok()?, end.parse().ok()?);
+						if start > end {
+							return None;
+						}
+						Some(ByteRange::FromTo(start, end))
+					}
+				}
+			})
+			.collect::<Option<Vec<_>>>()?;
+		if ranges.is_empty() {
+			return None;
+		}
+		Some(Range(ranges))
+	}
+
+	fn format(&self) -> String {
+		let ranges = self
+			.0
+			.iter()
+			.map(|range| match range {
+				ByteRange::FromTo(start, end) => format!("{}-{}", start, end),
+				ByteRange::From(start) => format!("{}-", start),
+				ByteRange::Last(suffix) => format!("-{}", suffix),
+			})
+			.collect::<Vec<_>>()
+			.join(", ");
+		format!("bytes={}", ranges)
+	}
+}
+
+/// The request headers that a response varies on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Vary {
+	/// `*`, which means the response can't be reused
+	Any,
+	Headers(Vec<String>),
+}
+
+impl Header for Vary {
+	const NAME: &'static str = "Vary";
+
+	fn parse(value: &str) -> Option<Self> {
+		if value.trim() == "*" {
+			Some(Vary::Any)
+		} else {
+			Some(Vary::Headers(
+				split_list(value).map(str::to_string).collect(),
+			))
+		}
+	}
+
+	fn format(&self) -> String {
+		match self {
+			Vary::Any => "*".to_string(),
+			Vary::Headers(headers) => headers.join(", "),
+		}
+	}
+}
+
+/// Splits a comma separated list, skipping empty entries
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+	value
+		.split(',')
+		.map(str::trim)
+		.filter(|entry| !entry.is_empty())
+}
+
+fn parse_quality_list(value: &str) -> Vec<QualityValue> {
+	split_list(value)
+		.map(|entry| {
+			let mut quality = 1.0;
+			let value = entry
+				.split(';')
+				.map(str::trim)
+				.filter(|part| match part.split_once('=') {
+					Some((key, value))
+						if key.trim().eq_ignore_ascii_case("q") =>
+					{
+						quality = value.trim().parse().unwrap_or(1.0);
+						false
+					}
+					_ => true,
+				})
+				.collect::<Vec<_>>()
+				.join(";");
+			QualityValue {
+				value,
+				quality: f32::clamp(quality, 0.0, 1.0),
+			}
+		})
+		.collect()
+}
+
+fn format_quality_list(values: &[QualityValue]) -> String {
+	values
+		.iter()
+		.map(|value| {
+			if value.quality >= 1.0 {
+				value.value.clone()
+			} else {
+				format!(
+					"{};q={}",
+					value.value,
+					(value.quality * 1000.0).round() / 1000.0
+				)
+			}
+		})
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// Splits a value with parameters, such as `attachment; filename="a;b.txt"`,
+/// into its parts. The first part is the value itself. Quoted parameter
+/// values are unquoted.
+fn split_params(value: &str) -> Vec<(String, Option<String>)> {
+	let mut parts = vec![];
+	let mut current = String::new();
+	let mut in_quotes = false;
+	let mut escaped = false;
+	for c in value.chars() {
+		if escaped {
+			escaped = false;
+		} else if in_quotes && c == '\\' {
+			escaped = true;
+		} else if c == '"' {
+			in_quotes = !in_quotes;
+		} else if c == ';' && !in_quotes {
+			parts.push(std::mem::take(&mut current));
+			continue;
+		}
+		current.push(c);
+	}
+	parts.push(current);
+
+	parts
+		.iter()
+		.map(|part| part.trim())
+		.filter(|part| !part.is_empty())
+		.map(|part| match part.split_once('=') {
+			Some((key, value)) => {
+				(key.trim().to_string(), Some(unquote(value.trim())))
+			}
+			None => (part.to_string(), None),
+		})
+		.collect()
+}
+
+fn unquote(value: &str) -> String {
+	match value
+		.strip_prefix('"')
+		.and_then(|value| value.strip_suffix('"'))
+	{
+		Some(value) => {
+			let mut unquoted = String::with_capacity(value.len());
+			let mut chars = value.chars();
+			while let Some(c) = chars.next() {
+				if c == '\\' {
+					unquoted.extend(chars.next());
+				} else {
+					unquoted.push(c);
+				}
+			}
+			unquoted
+		}
+		None => value.to_string(),
+	}
+}
+
+fn quote(value: &str) -> String {
+	format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_if_needed(value: &str) -> String {
+	let is_token = !value.is_empty() &&
+		value.chars().all(|c| {
+			c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+		});
+	if is_token {
+		value.to_string()
+	} else {
+		quote(value)
+	}
+}
+
+/// Decodes an RFC 8187 extended value, such as `UTF-8''na%C3%AFve.txt`
+fn decode_extended_value(value: &str) -> Option<String> {
+	let mut parts = value.splitn(3, '\'');
+	let charset = parts.next()?;
+	let _language = parts.next()?;
+	let encoded = parts.next()?;
+	if !charset.eq_ignore_ascii_case("utf-8") {
+		return None;
+	}
+	percent_decode_str(encoded)
+		.decode_utf8()
+		.ok()
+		.map(|decoded| decoded.to_string())
+}
+
+/// Parses a list of entity tags, such as `"abc", W/"def"`. Tags can contain
+/// commas, so the list can't simply be split.
+fn parse_etags(value: &str) -> Option<Vec<ETag>> {
+	let mut etags = vec![];
+	let mut rest = value.trim();
+	while !rest.is_empty() {
+		let (weak, tag) = match rest.strip_prefix("W/") {
+			Some(tag) => (true, tag),
+			None => (false, rest),
+		};
+		let tag = tag.strip_prefix('"')?;
+		let end = tag.find('"')?;
+		etags.push(ETag {
+			tag: tag[..end].to_string(),
+			weak,
+		});
+		rest = tag[end + 1..].trim_start();
+		rest = match rest.strip_prefix(',') {
+			Some(rest) => rest.trim_start(),
+			None if rest.is_empty() => rest,
+			None => return None,
+		};
+	}
+	if etags.is_empty() {
+		return None;
+	}
+	Some(etags)
+}
+
+/// Parses a date in any of the formats allowed in HTTP, which are the IMF
+/// fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`), RFC 850 and asctime formats
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+	let value = value.trim();
+	[
+		"%a, %d %b %Y %H:%M:%S GMT",
+		"%A, %d-%b-%y %H:%M:%S GMT",
+		"%a %b %e %H:%M:%S %Y",
+	]
+	.iter()
+	.find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+	.map(|date| Utc.from_utc_datetime(&date))
+}
+
+fn format_http_date(date: &DateTime<Utc>) -> String {
+	date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Parses a header that's already in its canonical form, and checks that
+	/// it's formatted back the same way
+	fn round_trip<THeader>(value: &str) -> THeader
+	where
+		THeader: Header + Debug + PartialEq,
+	{
+		let header = THeader::parse(value)
+			.unwrap_or_else(|| panic!("unable to parse `{}`", value));
+		assert_eq!(header.format(), value);
+		assert_eq!(THeader::parse(&header.format()).as_ref(), Some(&header));
+		header
+	}
+
+	#[test]
+	fn header_maps_ignore_the_case_of_names() {
+		let mut headers = HeaderMap::new();
+		headers.append("Accept", "text/html");
+		headers.append("accept", "application/json");
+		headers.set("X-Request-Id", "1");
+		headers.set("x-request-id", "2");
+		assert_eq!(
+			headers.get("ACCEPT").as_deref(),
+			Some("text/html, application/json")
+		);
+		assert_eq!(headers.get_all("x-request-id"), ["2"]);
+		assert_eq!(
+			headers.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+			["Accept", "X-Request-Id"]
+		);
+
+		assert_eq!(
+			headers.remove("ACCEPT").map(|values| values.len()),
+			Some(2)
+		);
+		assert!(!headers.contains("Accept"));
+		assert_eq!(headers.len(), 1);
+	}
+
+	#[test]
+	fn quality_lists_round_trip() {
+		let accept = round_trip::<Accept>("text/html;level=1, */*;q=0.5");
+		assert_eq!(
+			accept.0,
+			[
+				QualityValue::new("text/html;level=1", 1.0),
+				QualityValue::new("*/*", 0.5),
+			]
+		);
+
+		let encoding = AcceptEncoding::parse("gzip;q=2, br;q=nope").unwrap();
+		assert_eq!(encoding.format(), "gzip, br");
+	}
+
+	#[test]
+	fn dates_round_trip() {
+		let date = round_trip::<Date>("Sun, 06 Nov 1994 08:49:37 GMT");
+		assert_eq!(
+			IfModifiedSince::parse("Sunday, 06-Nov-94 08:49:37 GMT")
+				.map(|header| header.0),
+			Some(date.0)
+		);
+		assert_eq!(
+			LastModified::parse("Sun Nov  6 08:49:37 1994")
+				.map(|header| header.0),
+			Some(date.0)
+		);
+		assert_eq!(Expires::parse("0"), None);
+	}
+
+	#[test]
+	fn credentials_round_trip() {
+		assert_eq!(
+			round_trip::<Authorization>("Basic dXNlcjpwYTpzcw=="),
+			Authorization::Basic {
+				username: "user".to_string(),
+				password: "pa:ss".to_string(),
+			}
+		);
+		assert_eq!(
+			round_trip::<Authorization>("Bearer abc.def"),
+			Authorization::Bearer("abc.def".to_string())
+		);
+		round_trip::<Authorization>("Digest username=\"user\"");
+		assert_eq!(Authorization::parse("Basic !!!"), None);
+		assert_eq!(Authorization::parse("Bearer"), None);
+	}
+
+	#[test]
+	fn cache_control_round_trips() {
+		let cache_control = round_trip::<CacheControl>(
+			"no-cache, private, max-age=60, max-stale, community=\"a b\"",
+		);
+		assert!(cache_control.no_cache && cache_control.private);
+		assert_eq!(cache_control.max_age, Some(60));
+		assert_eq!(cache_control.max_stale, Some(u64::MAX));
+		assert_eq!(
+			cache_control.extensions,
+			[("community".to_string(), Some("a b".to_string()))]
+		);
+	}
+
+	#[test]
+	fn content_dispositions_round_trip() {
+		assert_eq!(
+			round_trip::<ContentDisposition>(
+				"attachment; filename=\"na_ve.txt\"; \
+				 filename*=UTF-8''na%C3%AFve.txt"
+			),
+			ContentDisposition::attachment(Some("naïve.txt"))
+		);
+		assert_eq!(
+			round_trip::<ContentDisposition>(
+				"form-data; name=\"field\"; filename=\"a;b \\\"c\\\".txt\""
+			),
+			ContentDisposition::form_data("field", Some("a;b \"c\".txt"))
+		);
+		assert_eq!(
+			ContentDisposition::parse("unknown")
+				.map(|header| header.disposition),
+			Some(DispositionType::Attachment)
+		);
+	}
+
+	#[test]
+	fn content_headers_round_trip() {
+		assert_eq!(round_trip::<ContentLength>("1234"), ContentLength(1234));
+		assert_eq!(
+			round_trip::<ContentRange>("bytes 0-499/1234").range,
+			Some((0, 499))
+		);
+		assert_eq!(round_trip::<ContentRange>("bytes */1234").range, None);
+		assert_eq!(ContentRange::parse("bytes 5-1/10"), None);
+
+		let content_type =
+			round_trip::<ContentType>("text/html; charset=utf-8");
+		assert_eq!(content_type.get_charset(), Some("utf-8"));
+		round_trip::<ContentType>("multipart/form-data; boundary=\"a b\"");
+		assert_eq!(ContentType::parse("text"), None);
+	}
+
+	#[test]
+	fn etags_round_trip() {
+		assert_eq!(round_trip::<ETag>("W/\"abc\""), ETag::weak("abc"));
+		assert_eq!(ETag::parse("\"a\", \"b\""), None);
+
+		let if_none_match = round_trip::<IfNoneMatch>("\"a,b\", W/\"c\"");
+		assert!(if_none_match.matches(&ETag::strong("c")));
+		assert!(!if_none_match.matches(&ETag::strong("a")));
+		let if_match = round_trip::<IfMatch>("\"a,b\", W/\"c\"");
+		assert!(if_match.matches(&ETag::strong("a,b")));
+		assert!(!if_match.matches(&ETag::strong("c")));
+		assert_eq!(round_trip::<IfMatch>("*"), IfMatch::Any);
+	}
+
+	#[test]
+	fn ranges_round_trip() {
+		let range = round_trip::<Range>("bytes=0-499, 1000-, -500");
+		assert_eq!(
+			range.0,
+			[
+				ByteRange::FromTo(0, 499),
+				ByteRange::From(1000),
+				ByteRange::Last(500),
+			]
+		);
+		assert_eq!(range.0[0].resolve(100), Some((0, 99)));
+		assert_eq!(range.0[1].resolve(100), None);
+		assert_eq!(range.0[2].resolve(100), Some((0, 99)));
+		assert_eq!(Range::parse("bytes=5-1"), None);
+		assert_eq!(Range::parse("items=0-1"), None);
+	}
+
+	#[test]
+	fn lists_round_trip() {
+		assert_eq!(
+			round_trip::<Allow>("GET, POST").0,
+			[HttpMethod::Get, HttpMethod::Post]
+		);
+		assert_eq!(
+			round_trip::<Vary>("Accept-Encoding, Origin"),
+			Vary::Headers(vec![
+				"Accept-Encoding".to_string(),
+				"Origin".to_string()
+			])
+		);
+		assert_eq!(round_trip::<Vary>("*"), Vary::Any);
+	}
+}
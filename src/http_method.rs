@@ -79,3 +79,19 @@ impl From<Method> for HttpMethod {
 		}
 	}
 }
+
+impl From<HttpMethod> for Method {
+	fn from(method: HttpMethod) -> Self {
+		match method {
+			HttpMethod::Get => Method::GET,
+			HttpMethod::Post => Method::POST,
+			HttpMethod::Put => Method::PUT,
+			HttpMethod::Delete => Method::DELETE,
+			HttpMethod::Head => Method::HEAD,
+			HttpMethod::Options => Method::OPTIONS,
+			HttpMethod::Connect => Method::CONNECT,
+			HttpMethod::Patch => Method::PATCH,
+			HttpMethod::Trace => Method::TRACE,
+		}
+	}
+}
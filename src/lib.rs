@@ -5,25 +5,48 @@ mod error;
 mod http_method;
 mod middleware;
 mod middleware_handler;
-mod request;
-mod response;
-//mod headers;
+mod negotiation;
+mod proxy_protocol;
+mod pub_sub;
+mod query;
 #[cfg(feature = "render")]
 mod renderer;
+mod request;
+mod response;
+mod server_timing;
+mod service;
+mod sse;
+mod test_client;
+#[cfg(feature = "tower")]
+mod tower;
+#[cfg(feature = "tracing")]
+mod trace_context;
+mod trusted_proxies;
+#[cfg(feature = "websocket")]
+mod websocket;
 
 pub mod default_middlewares;
+pub mod headers;
 
-use std::{fmt::Debug, net::SocketAddr, sync::Arc};
+use std::{
+	any::Any,
+	fmt::Debug,
+	io::Error as IoError,
+	net::SocketAddr,
+	panic::AssertUnwindSafe,
+	sync::Arc,
+};
 
 pub use app::App;
 pub use context::{default_context_generator, Context, DefaultContext};
 pub use cookie::{Cookie, CookieOptions, SameSite};
 pub use error::{AsError, DefaultError, Error};
-use futures::Future;
+use futures::{Future, FutureExt};
 pub use handlebars;
 pub use http_method::HttpMethod;
+pub use hyper::upgrade::{OnUpgrade, Upgraded};
 use hyper::{
-	server::conn::AddrStream,
+	server::{accept, conn::AddrStream},
 	service::{make_service_fn, service_fn},
 	Body,
 	Error as HyperError,
@@ -32,10 +55,34 @@ use hyper::{
 	Server,
 	StatusCode,
 };
+pub use ipnet::IpNet;
 pub use middleware::{DefaultMiddleware, Middleware, NextHandler};
+use proxy_protocol::ProxiedStream;
+pub use proxy_protocol::ProxyProtocol;
+pub use pub_sub::{Backpressure, PubSub, Subscription, DEFAULT_TOPIC_CAPACITY};
+pub use query::{QueryError, QueryMap};
 pub use renderer::RenderEngine;
-pub use request::Request;
+pub use request::{BodyError, Request};
 pub use response::Response;
+pub use server_timing::ServerTiming;
+pub use service::ServiceResponse;
+pub use sse::{SseEvent, SseSender};
+pub use test_client::{TestClient, TestRequest, TestResponse};
+use tokio::net::TcpListener;
+pub use tokio_util::sync::CancellationToken;
+#[cfg(feature = "tower")]
+pub use tower::{
+	AppMakeService,
+	AppService,
+	NextService,
+	TowerLayer,
+	TowerService,
+};
+#[cfg(feature = "tracing")]
+pub use trace_context::TraceContext;
+pub use trusted_proxies::{ForwardedHeaders, TrustedProxies};
+#[cfg(feature = "websocket")]
+pub use websocket::{tungstenite, Message, WebSocket, WebSocketHandlerFn};
 
 pub async fn listen<
 	TContext,
@@ -57,9 +104,11 @@ pub async fn listen<
 	TErrorData: 'static + Default + Send + Sync,
 	TListenAddr: Into<SocketAddr>,
 {
+	let proxy_protocol = app.get_proxy_protocol();
 	let app_arc = Arc::new(app);
+	let bind_addr = bind_addr.into();
 
-	async move {
+	if proxy_protocol == ProxyProtocol::Disabled {
 		let service = make_service_fn(|conn: &AddrStream| {
 			let app = app_arc.clone();
 			let remote_addr = conn.remote_addr();
@@ -67,65 +116,43 @@ pub async fn listen<
 			async move {
 				Ok::<_, HyperError>(service_fn(
 					move |req: HyperRequest<Body>| {
-						let app = app.clone();
-						async move {
-							let request =
-								Request::from_hyper(remote_addr, req).await;
-							let mut context = app.generate_context(request);
-							context.header("Server", "Eve");
-
-							// execute app's middlewares
-							let result = app.resolve(context).await;
-							let response = match result {
-								Ok(context) => context.take_response(),
-								Err(err) => {
-									// return a proper formatted error, if an
-									// error handler exists
-									if let Some(handler) = app.error_handler {
-										let response = Response::new();
-										handler(response, err)
-									} else {
-										let mut hyper_response =
-											HyperResponse::new(Body::from(
-												Vec::from(
-													err.get_body_bytes()
-														.unwrap_or_else(|| {
-															"Internal server error"
-															.as_bytes()
-														}),
-												),
-											));
-										*hyper_response.status_mut() =
-										StatusCode::from_u16(err.get_status().unwrap_or(500))
-											.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
-										return Ok(hyper_response);
-									}
-								}
-							};
-
-							let mut hyper_response = HyperResponse::builder();
-
-							// Set the appropriate headers
-							for (key, values) in &response.headers {
-								for value in values {
-									hyper_response =
-										hyper_response.header(key, value);
-								}
-							}
-
-							Ok::<HyperResponse<Body>, HyperError>(
-								hyper_response
-									.status(response.status)
-									.body(Body::from(response.body))
-									.unwrap(),
-							)
-						}
+						handle_request(app.clone(), remote_addr, req)
+					},
+				))
+			}
+		});
+
+		let server = Server::bind(&bind_addr).serve(service);
+
+		if let Some(shutdown_signal) = shutdown_signal {
+			server
+				.with_graceful_shutdown(shutdown_signal)
+				.await
+				.unwrap();
+		} else {
+			server.await.unwrap();
+		}
+	} else {
+		// the remote address is taken from the PROXY protocol header instead
+		// of the socket
+		let service = make_service_fn(|conn: &ProxiedStream| {
+			let app = app_arc.clone();
+			let remote_addr = conn.remote_addr();
+
+			async move {
+				Ok::<_, HyperError>(service_fn(
+					move |req: HyperRequest<Body>| {
+						handle_request(app.clone(), remote_addr, req)
 					},
 				))
 			}
 		});
 
-		let server = Server::bind(&bind_addr.into()).serve(service);
+		let listener = TcpListener::bind(bind_addr).await.unwrap();
+		let server = Server::builder(accept::from_stream(
+			proxy_protocol::accept(listener, proxy_protocol),
+		))
+		.serve(service);
 
 		if let Some(shutdown_signal) = shutdown_signal {
 			server
@@ -136,5 +163,119 @@ pub async fn listen<
 			server.await.unwrap();
 		}
 	}
-	.await
+}
+
+/// Runs a single request through the app, producing the response that's sent
+/// to the client
+pub(crate) async fn handle_request<TContext, TMiddleware, TState, TErrorData>(
+	app: Arc<App<TContext, TMiddleware, TState, TErrorData>>,
+	remote_addr: SocketAddr,
+	req: HyperRequest<Body>,
+) -> Result<HyperResponse<Body>, HyperError>
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TMiddleware:
+		'static + Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TState: 'static + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	let method = HttpMethod::from(req.method().clone());
+	let path = req.uri().path().to_string();
+	// only worked out when something goes wrong, so that it isn't on the
+	// path of every request
+	let get_route = |app: &App<TContext, TMiddleware, TState, TErrorData>| {
+		app.get_route(&method, &path)
+			.unwrap_or_else(|| path.clone())
+	};
+
+	// fired if the client disconnects (and this future is dropped) before
+	// the response is ready
+	let cancellation_token = CancellationToken::new();
+	let cancel_guard = cancellation_token.clone().drop_guard();
+
+	// execute app's middlewares, converting any panic into an error so that
+	// the client gets a response
+	let resolve = {
+		let app = app.clone();
+		AssertUnwindSafe(async move {
+			let mut request = Request::from_hyper(remote_addr, req);
+			request.cancellation_token = cancellation_token;
+			let mut context = app.generate_context(request);
+			context.header("Server", "Eve");
+
+			app.resolve(context).await
+		})
+		.catch_unwind()
+	};
+	let result = if app.get_cancel_on_disconnect() {
+		resolve.await
+	} else {
+		// run the middlewares in their own task, so that they aren't dropped
+		// along with this future
+		tokio::spawn(resolve).await.unwrap_or_else(|err| {
+			Err(Box::new(err.to_string()) as Box<dyn Any + Send>)
+		})
+	}
+	.unwrap_or_else(|panic| {
+		let message = get_panic_message(&panic);
+		log::error!(
+			"Panic while handling {} {}: {}",
+			method,
+			get_route(&app),
+			message
+		);
+		Err(Error::<TErrorData>::new(Box::new(IoError::other(message)))
+			.status(500))
+	});
+	cancel_guard.disarm();
+
+	let response = match result {
+		Ok(context) => app.take_response(context),
+		Err(err) => {
+			let mut response = Response::new();
+			for (key, values) in err.get_headers().iter() {
+				for value in values {
+					response.append_header(key, value);
+				}
+			}
+			// return a proper formatted error, if an error handler exists
+			if let Some(handler) = app.error_handler {
+				handler(response, err)
+			} else {
+				response.set_status(
+					StatusCode::from_u16(err.get_status().unwrap_or(500))
+						.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+						.as_u16(),
+				);
+				response.set_body_bytes(
+					err.get_body_bytes()
+						.unwrap_or_else(|| "Internal server error".as_bytes()),
+				);
+				response
+			}
+		}
+	};
+
+	Ok(response.into_hyper_response().unwrap_or_else(|err| {
+		log::error!(
+			"Invalid response while handling {} {}: {}",
+			method,
+			get_route(&app),
+			err
+		);
+		let mut hyper_response =
+			HyperResponse::new(Body::from("Internal server error"));
+		*hyper_response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+		hyper_response
+	}))
+}
+
+fn get_panic_message(panic: &Box<dyn Any + Send>) -> String {
+	if let Some(message) = panic.downcast_ref::<&str>() {
+		message.to_string()
+	} else if let Some(message) = panic.downcast_ref::<String>() {
+		message.clone()
+	} else {
+		"unknown panic".to_string()
+	}
 }
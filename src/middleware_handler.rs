@@ -1,4 +1,4 @@
-use std::{fmt::Debug, marker::PhantomData};
+use std::{collections::HashMap, fmt::Debug, marker::PhantomData};
 
 use regex::Regex;
 
@@ -49,68 +49,12 @@ where
 		handler: TMiddleware,
 		is_endpoint: bool,
 	) -> Self {
-		let mut mounted_url = path.to_string();
-
-		// Make sure it always begins with a /
-		if mounted_url.starts_with("./") {
-			mounted_url = mounted_url[1..].to_string();
-		} else if !path.starts_with('/') {
-			mounted_url = format!("/{}", mounted_url);
-		}
-
-		// if there's a trailing /, remove it
-		if mounted_url.ends_with('/') {
-			mounted_url = path[..(path.len() - 1)].to_string();
-		}
-
-		// If there's nothing left, set the middleware to /
-		if mounted_url.is_empty() {
-			mounted_url.push('/');
-		}
-
-		let mut regex_path = mounted_url
-			.replace('\\', "\\\\")
-			.replace('[', "\\[")
-			.replace(']', "\\]")
-			.replace('?', "\\?")
-			.replace('+', "\\+")
-			.replace('{', "\\{")
-			.replace('}', "\\}")
-			.replace('(', "\\(")
-			.replace(')', "\\)")
-			.replace('|', "\\|")
-			.replace('^', "\\^")
-			.replace('$', "\\$")
-			.replace('.', "\\.") // Specifically, match the dot. This ain't a regex character
-			.replace("**", "(.+)") // Match anything [ NOTE: first replace `**` and then replace
-			// remaining `*` ]
-			.replace('*', "([^/]+)"); // Match anything that's not a /, but at least 1 character
-
-		// Make a variable out of anything that begins with a : and has a-z,
-		// A-Z, 0-9, '_'
-		regex_path = Regex::new(":(?P<var>([a-zA-Z0-9_]+))")
-			.unwrap()
-			// Match that variable with anything that isn't a `/`
-			.replace_all(&regex_path, "(?P<$var>([^\\s/]+))")
-			.to_string();
-
-		if regex_path != "/" {
-			// If there's something to match with,
-			// add the Regex to mention that both / and non / should match at
-			// the end of the url
-			regex_path.push_str("[/]?");
-		}
-
-		// If this is only supposed to match an endpoint URL, make sure the
-		// Regex only allows the end of the path
-		if is_endpoint {
-			regex_path.push('$');
-		}
+		let (mounted_url, path_match) = get_path_match(path, is_endpoint);
 
 		MiddlewareHandler {
 			is_endpoint,
 			mounted_url,
-			path_match: Regex::new(&regex_path).unwrap(),
+			path_match,
 			handler,
 			phantom_context: PhantomData,
 			phantom_error: PhantomData,
@@ -121,3 +65,84 @@ where
 		self.path_match.is_match(url)
 	}
 }
+
+/// Normalises the path a handler is mounted on and builds the Regex used to
+/// match request paths against it
+pub(crate) fn get_path_match(path: &str, is_endpoint: bool) -> (String, Regex) {
+	let mut mounted_url = path.to_string();
+
+	// Make sure it always begins with a /
+	if mounted_url.starts_with("./") {
+		mounted_url = mounted_url[1..].to_string();
+	} else if !path.starts_with('/') {
+		mounted_url = format!("/{}", mounted_url);
+	}
+
+	// if there's a trailing /, remove it
+	if mounted_url.ends_with('/') {
+		mounted_url = path[..(path.len() - 1)].to_string();
+	}
+
+	// If there's nothing left, set the middleware to /
+	if mounted_url.is_empty() {
+		mounted_url.push('/');
+	}
+
+	let mut regex_path = mounted_url
+		.replace('\\', "\\\\")
+		.replace('[', "\\[")
+		.replace(']', "\\]")
+		.replace('?', "\\?")
+		.replace('+', "\\+")
+		.replace('{', "\\{")
+		.replace('}', "\\}")
+		.replace('(', "\\(")
+		.replace(')', "\\)")
+		.replace('|', "\\|")
+		.replace('^', "\\^")
+		.replace('$', "\\$")
+		.replace('.', "\\.") // Specifically, match the dot. This ain't a regex character
+		.replace("**", "(.+)") // Match anything [ NOTE: first replace `**` and then replace
+		// remaining `*` ]
+		.replace('*', "([^/]+)"); // Match anything that's not a /, but at least 1 character
+
+	// Make a variable out of anything that begins with a : and has a-z,
+	// A-Z, 0-9, '_'
+	regex_path = Regex::new(":(?P<var>([a-zA-Z0-9_]+))")
+		.unwrap()
+		// Match that variable with anything that isn't a `/`
+		.replace_all(&regex_path, "(?P<$var>([^\\s/]+))")
+		.to_string();
+
+	if regex_path != "/" {
+		// If there's something to match with,
+		// add the Regex to mention that both / and non / should match at
+		// the end of the url
+		regex_path.push_str("[/]?");
+	}
+
+	// If this is only supposed to match an endpoint URL, make sure the
+	// Regex only allows the end of the path
+	if is_endpoint {
+		regex_path.push('$');
+	}
+
+	let path_match = Regex::new(&regex_path).unwrap();
+	(mounted_url, path_match)
+}
+
+/// Extracts the named url parameters of a path from its match
+pub(crate) fn get_url_params(
+	path_match: &Regex,
+	path: &str,
+) -> HashMap<String, String> {
+	let mut url_params = HashMap::new();
+	if let Some(captures) = path_match.captures(path) {
+		for var in path_match.capture_names().flatten() {
+			if let Some(value) = captures.name(var) {
+				url_params.insert(var.to_string(), value.as_str().to_string());
+			}
+		}
+	}
+	url_params
+}
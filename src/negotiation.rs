@@ -0,0 +1,373 @@
+/// An entry of an `Accept*` header, such as `text/html;q=0.8`
+struct Preference {
+	value: String,
+	quality: f32,
+	index: usize,
+}
+
+/// How well an entry of an `Accept*` header matches an offered value. Exact
+/// matches are preferred over wildcards with the same quality.
+type Matcher = fn(accepted: &str, offered: &str) -> Option<u8>;
+
+/// Returns the offered type that the `Accept` header prefers. Types can be
+/// full media types, or extensions such as `json` and `html`.
+pub(crate) fn accepts(
+	header: Option<String>,
+	offered: &[&str],
+) -> Option<String> {
+	negotiate(header, offered, |accepted, offered| {
+		match_media_type(accepted, &expand_media_type(offered))
+	})
+}
+
+/// Returns the offered encoding that the `Accept-Encoding` header prefers.
+/// `identity` is acceptable unless the header explicitly refuses it.
+pub(crate) fn accepts_encodings(
+	header: Option<String>,
+	offered: &[&str],
+) -> Option<String> {
+	// Only identity is acceptable without the header
+	let preferences = parse_header(header.as_deref().unwrap_or_default());
+	let refuses_identity = preferences.iter().any(|preference| {
+		(preference.value == "identity" || preference.value == "*") &&
+			preference.quality == 0.0
+	});
+	best_match(&preferences, offered, match_token).or_else(|| {
+		offered
+			.iter()
+			.find(|offered| offered.eq_ignore_ascii_case("identity"))
+			.filter(|_| !refuses_identity)
+			.map(|offered| offered.to_string())
+	})
+}
+
+pub(crate) fn accepts_charsets(
+	header: Option<String>,
+	offered: &[&str],
+) -> Option<String> {
+	negotiate(header, offered, match_token)
+}
+
+/// Returns the offered language that the `Accept-Language` header prefers.
+/// A language range such as `en` matches more specific tags like `en-US`,
+/// and the other way around.
+pub(crate) fn accepts_languages(
+	header: Option<String>,
+	offered: &[&str],
+) -> Option<String> {
+	negotiate(header, offered, |accepted, offered| {
+		if accepted == "*" {
+			Some(0)
+		} else if accepted.eq_ignore_ascii_case(offered) {
+			Some(2)
+		} else if is_language_prefix(accepted, offered) ||
+			is_language_prefix(offered, accepted)
+		{
+			Some(1)
+		} else {
+			None
+		}
+	})
+}
+
+/// Returns true if the content type matches the pattern. Patterns can be full
+/// media types, wildcards such as `text/*`, suffixes such as `+json` or
+/// extensions such as `json`.
+pub(crate) fn is_type(content_type: &str, pattern: &str) -> bool {
+	let content_type = content_type.trim();
+	if let Some(suffix) = pattern.strip_prefix('+') {
+		return content_type
+			.rsplit_once('+')
+			.map(|(_, content_suffix)| {
+				content_suffix.eq_ignore_ascii_case(suffix)
+			})
+			.unwrap_or(false);
+	}
+	match_media_type(&expand_media_type(pattern), content_type).is_some()
+}
+
+/// Picks the offered value that the header prefers. Every offered value is
+/// acceptable if the header isn't set, so the first one is returned.
+fn negotiate(
+	header: Option<String>,
+	offered: &[&str],
+	matcher: Matcher,
+) -> Option<String> {
+	let Some(header) = header else {
+		return offered.first().map(|offered| offered.to_string());
+	};
+	best_match(&parse_header(&header), offered, matcher)
+}
+
+/// Orders the offered values by the quality of the entry that matches them
+/// most specifically, then by the order of the entries in the header and
+/// finally by the order they're offered in
+fn best_match(
+	preferences: &[Preference],
+	offered: &[&str],
+	matcher: Matcher,
+) -> Option<String> {
+	offered
+		.iter()
+		.enumerate()
+		.filter_map(|(offered_index, offered)| {
+			let (preference, specificity) = preferences
+				.iter()
+				.filter_map(|preference| {
+					matcher(&preference.value, offered)
+						.map(|specificity| (preference, specificity))
+				})
+				.max_by(|(a, a_specificity), (b, b_specificity)| {
+					a_specificity.cmp(b_specificity).then(b.index.cmp(&a.index))
+				})?;
+			if preference.quality <= 0.0 {
+				return None;
+			}
+			Some((
+				preference.quality,
+				specificity,
+				preference.index,
+				offered_index,
+				offered,
+			))
+		})
+		.max_by(|a, b| {
+			a.0.total_cmp(&b.0)
+				.then(a.1.cmp(&b.1))
+				.then(b.2.cmp(&a.2))
+				.then(b.3.cmp(&a.3))
+		})
+		.map(|(.., offered)| offered.to_string())
+}
+
+fn parse_header(header: &str) -> Vec<Preference> {
+	header
+		.split(',')
+		.enumerate()
+		.filter_map(|(index, entry)| {
+			let mut parts = entry.split(';');
+			let value = parts.next()?.trim();
+			if value.is_empty() {
+				return None;
+			}
+			let quality = parts
+				.filter_map(|param| {
+					let (key, value) = param.split_once('=')?;
+					if key.trim().eq_ignore_ascii_case("q") {
+						value.trim().parse::<f32>().ok()
+					} else {
+						None
+					}
+				})
+				.next()
+				.unwrap_or(1.0)
+				.clamp(0.0, 1.0);
+			Some(Preference {
+				value: value.to_lowercase(),
+				quality,
+				index,
+			})
+		})
+		.collect()
+}
+
+fn match_token(accepted: &str, offered: &str) -> Option<u8> {
+	if accepted == "*" {
+		Some(0)
+	} else if accepted.eq_ignore_ascii_case(offered) {
+		Some(1)
+	} else {
+		None
+	}
+}
+
+fn is_language_prefix(prefix: &str, tag: &str) -> bool {
+	tag.len() > prefix.len() &&
+		tag.is_char_boundary(prefix.len()) &&
+		tag[..prefix.len()].eq_ignore_ascii_case(prefix) &&
+		tag.as_bytes()[prefix.len()] == b'-'
+}
+
+/// Matches a media range, such as `text/*`, against a media type. Either
+/// side's subtype may be a wildcard with a suffix, such as `*+json`.
+fn match_media_type(range: &str, media_type: &str) -> Option<u8> {
+	let (range_type, range_subtype) = split_media_type(range)?;
+	let (media_type, media_subtype) = split_media_type(media_type)?;
+
+	if range_type == "*" && range_subtype == "*" {
+		return Some(0);
+	}
+	if range_type != "*" && !range_type.eq_ignore_ascii_case(media_type) {
+		return None;
+	}
+	if range_subtype == "*" {
+		Some(1)
+	} else if range_subtype.eq_ignore_ascii_case(media_subtype) {
+		Some(3)
+	} else if let Some(suffix) = range_subtype.strip_prefix("*+") {
+		media_subtype
+			.rsplit_once('+')
+			.filter(|(_, media_suffix)| {
+				media_suffix.eq_ignore_ascii_case(suffix)
+			})
+			.map(|_| 2)
+	} else {
+		None
+	}
+}
+
+fn split_media_type(media_type: &str) -> Option<(&str, &str)> {
+	let media_type = media_type.split(';').next()?.trim();
+	media_type.split_once('/')
+}
+
+/// Expands extensions, such as `json`, to their media types
+fn expand_media_type(value: &str) -> String {
+	if value.contains('/') {
+		return value.to_string();
+	}
+	match value.trim_start_matches('.').to_lowercase().as_str() {
+		"html" | "htm" => "text/html",
+		"text" | "txt" => "text/plain",
+		"css" => "text/css",
+		"csv" => "text/csv",
+		"js" => "application/javascript",
+		"json" => "application/json",
+		"xml" => "application/xml",
+		"urlencoded" => "application/x-www-form-urlencoded",
+		"multipart" => "multipart/*",
+		"pdf" => "application/pdf",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"svg" => "image/svg+xml",
+		"webp" => "image/webp",
+		_ => return format!("application/{}", value),
+	}
+	.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn accept(header: &str, offered: &[&str]) -> Option<String> {
+		accepts(Some(header.to_string()), offered)
+	}
+
+	#[test]
+	fn types_are_ordered_by_quality() {
+		assert_eq!(
+			accept("text/html;q=0.8, application/json", &["html", "json"]),
+			Some("json".to_string())
+		);
+		assert_eq!(
+			accept(
+				"text/html;level=1;q=0.3, text/plain;q=2",
+				&["html", "text"]
+			),
+			Some("text".to_string())
+		);
+		// Equal qualities are ordered by the header, then by the offers
+		assert_eq!(
+			accept("application/json, text/html", &["html", "json"]),
+			Some("json".to_string())
+		);
+		assert_eq!(accept("*/*", &["html", "json"]), Some("html".to_string()));
+	}
+
+	#[test]
+	fn specific_types_override_wildcards() {
+		let header = "*/*;q=0.1, text/*;q=0.5, text/html";
+		assert_eq!(
+			accept(header, &["application/json", "text/plain", "text/html"]),
+			Some("text/html".to_string())
+		);
+		assert_eq!(
+			accept(header, &["application/json", "text/plain"]),
+			Some("text/plain".to_string())
+		);
+		assert_eq!(
+			accept(header, &["application/json"]),
+			Some("application/json".to_string())
+		);
+
+		// A refused type stays refused even though a wildcard accepts it
+		assert_eq!(
+			accept("text/*, text/html;q=0", &["text/html", "text/plain"]),
+			Some("text/plain".to_string())
+		);
+		assert_eq!(accept("text/*, text/html;q=0", &["text/html"]), None);
+
+		assert_eq!(
+			accept("application/*+json", &["xml", "application/ld+json"]),
+			Some("application/ld+json".to_string())
+		);
+		assert_eq!(accept("image/png", &["json"]), None);
+	}
+
+	#[test]
+	fn missing_headers_accept_the_first_offer() {
+		assert_eq!(accepts(None, &["json", "html"]), Some("json".to_string()));
+		assert_eq!(accepts(None, &[]), None);
+	}
+
+	#[test]
+	fn identity_is_acceptable_unless_refused() {
+		let encoding = |header: Option<&str>, offered: &[&str]| {
+			accepts_encodings(header.map(String::from), offered)
+		};
+		assert_eq!(
+			encoding(Some("gzip;q=0.5, br"), &["gzip", "br"]),
+			Some("br".to_string())
+		);
+		assert_eq!(
+			encoding(None, &["gzip", "identity"]),
+			Some("identity".to_string())
+		);
+		assert_eq!(
+			encoding(Some("gzip"), &["br", "identity"]),
+			Some("identity".to_string())
+		);
+		assert_eq!(encoding(Some("*;q=0"), &["identity"]), None);
+		assert_eq!(encoding(Some("identity;q=0, gzip"), &["identity"]), None);
+	}
+
+	#[test]
+	fn languages_match_by_prefix() {
+		let language = |header: &str, offered: &[&str]| {
+			accepts_languages(Some(header.to_string()), offered)
+		};
+		assert_eq!(
+			language("en;q=0.8, fr", &["en-US", "fr-CA"]),
+			Some("fr-CA".to_string())
+		);
+		assert_eq!(language("en-US", &["en"]), Some("en".to_string()));
+		assert_eq!(
+			language("en-US, en;q=0.5", &["en-GB", "en-US"]),
+			Some("en-US".to_string())
+		);
+		assert_eq!(language("english", &["en"]), None);
+	}
+
+	#[test]
+	fn charsets_match_exactly() {
+		assert_eq!(
+			accepts_charsets(
+				Some("utf-8, iso-8859-1;q=0.5".to_string()),
+				&["iso-8859-1", "UTF-8"]
+			),
+			Some("UTF-8".to_string())
+		);
+	}
+
+	#[test]
+	fn types_are_matched_by_pattern() {
+		assert!(is_type("application/json; charset=utf-8", "json"));
+		assert!(is_type("application/ld+json", "+json"));
+		assert!(is_type("text/html", "text/*"));
+		assert!(is_type("multipart/form-data", "multipart"));
+		assert!(!is_type("text/html", "json"));
+		assert!(!is_type("application/json", "+json"));
+	}
+}
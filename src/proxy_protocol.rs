@@ -0,0 +1,472 @@
+use std::{
+	io::{Error as IoError, ErrorKind, Result as IoResult},
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	pin::Pin,
+	str::FromStr,
+	task::{Context as PollContext, Poll},
+	time::Duration,
+};
+
+use futures::{stream, Stream};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+	net::{TcpListener, TcpStream},
+	sync::mpsc,
+	time,
+};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// A v1 header can't be longer than 107 bytes, including the CRLF
+const V1_MAX_LENGTH: usize = 107;
+const V2_HEADER_LENGTH: usize = 16;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether connections accepted by [`listen`](crate::listen) start with a
+/// PROXY protocol (v1 or v2) header that carries the real client address.
+///
+/// The header isn't authenticated, so anyone who can connect to the app can
+/// send one and pick the address their requests appear to come from. Only
+/// enable it when the app can't be reached except through the proxy, such as
+/// by firewalling its port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ProxyProtocol {
+	/// Connections are used as they are
+	#[default]
+	Disabled,
+	/// The header is read if a connection starts with one. Connections
+	/// without a header use the socket peer as the client address. Clients
+	/// that connect directly can still send a header of their own, so this
+	/// is no safer than `Required` when the app is reachable without the
+	/// proxy.
+	Optional,
+	/// Connections that don't start with a valid header are closed
+	Required,
+}
+
+/// A connection whose PROXY protocol header has been read. Any bytes that
+/// were read past the header are replayed before reading from the socket.
+pub(crate) struct ProxiedStream {
+	inner: TcpStream,
+	remote_addr: SocketAddr,
+	buffered: Vec<u8>,
+	position: usize,
+}
+
+impl ProxiedStream {
+	/// The client address reported by the proxy, or the socket peer if the
+	/// proxy didn't report one
+	pub(crate) fn remote_addr(&self) -> SocketAddr {
+		self.remote_addr
+	}
+}
+
+impl AsyncRead for ProxiedStream {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<IoResult<()>> {
+		if self.position < self.buffered.len() {
+			let remaining = &self.buffered[self.position..];
+			let length = remaining.len().min(buf.remaining());
+			buf.put_slice(&remaining[..length]);
+			self.position += length;
+			return Poll::Ready(Ok(()));
+		}
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for ProxiedStream {
+	fn poll_write(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+		buf: &[u8],
+	) -> Poll<IoResult<usize>> {
+		Pin::new(&mut self.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+	) -> Poll<IoResult<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+	) -> Poll<IoResult<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
+	}
+}
+
+/// Accepts connections on the listener and reads their PROXY protocol
+/// headers. Headers are read concurrently, so a slow client doesn't hold up
+/// the connections accepted after it.
+pub(crate) fn accept(
+	listener: TcpListener,
+	proxy_protocol: ProxyProtocol,
+) -> impl Stream<Item = Result<ProxiedStream, IoError>> {
+	let (sender, receiver) = mpsc::channel(64);
+
+	tokio::spawn(async move {
+		loop {
+			let (stream, peer) = tokio::select! {
+				accepted = listener.accept() => match accepted {
+					Ok(accepted) => accepted,
+					Err(err) => {
+						// Usually caused by running out of file descriptors,
+						// so give the other connections time to close
+						log::error!("Unable to accept a connection: {}", err);
+						time::sleep(Duration::from_millis(100)).await;
+						continue;
+					}
+				},
+				// The server has shut down
+				_ = sender.closed() => return,
+			};
+
+			let sender = sender.clone();
+			tokio::spawn(async move {
+				let header = time::timeout(
+					HEADER_TIMEOUT,
+					read_header(stream, peer, proxy_protocol),
+				)
+				.await
+				.unwrap_or_else(|_| {
+					Err(IoError::new(
+						ErrorKind::TimedOut,
+						"timed out reading the PROXY protocol header",
+					))
+				});
+				match header {
+					Ok(stream) => {
+						let _ = sender.send(stream).await;
+					}
+					Err(err) => {
+						log::warn!(
+							"Rejected connection from {}: {}",
+							peer,
+							err
+						);
+					}
+				}
+			});
+		}
+	});
+
+	stream::unfold(receiver, |mut receiver| async move {
+		let stream = receiver.recv().await?;
+		Some((Ok(stream), receiver))
+	})
+}
+
+enum Header {
+	/// More bytes are needed to parse the header
+	Incomplete,
+	/// The connection doesn't start with a header
+	Missing,
+	/// A header of the given length. The address is `None` if the header
+	/// doesn't carry the client address, such as for health checks made by
+	/// the proxy itself.
+	Parsed {
+		length: usize,
+		source: Option<SocketAddr>,
+	},
+}
+
+async fn read_header(
+	mut stream: TcpStream,
+	peer: SocketAddr,
+	proxy_protocol: ProxyProtocol,
+) -> IoResult<ProxiedStream> {
+	let mut buffered = Vec::with_capacity(V1_MAX_LENGTH);
+	let mut chunk = [0; 512];
+
+	let (position, remote_addr) = loop {
+		match parse_header(&buffered)? {
+			Header::Incomplete => (),
+			Header::Missing if proxy_protocol == ProxyProtocol::Required => {
+				return Err(IoError::new(
+					ErrorKind::InvalidData,
+					"missing PROXY protocol header",
+				));
+			}
+			Header::Missing => break (0, peer),
+			Header::Parsed { length, source } => {
+				break (length, source.unwrap_or(peer));
+			}
+		}
+
+		let read = stream.read(&mut chunk).await?;
+		if read == 0 {
+			return Err(ErrorKind::UnexpectedEof.into());
+		}
+		buffered.extend_from_slice(&chunk[..read]);
+	};
+
+	Ok(ProxiedStream {
+		inner: stream,
+		remote_addr,
+		buffered,
+		position,
+	})
+}
+
+fn parse_header(buffer: &[u8]) -> IoResult<Header> {
+	if buffer.starts_with(V2_SIGNATURE) {
+		parse_v2(buffer)
+	} else if buffer.starts_with(V1_PREFIX) {
+		parse_v1(buffer)
+	} else if V2_SIGNATURE.starts_with(buffer) || V1_PREFIX.starts_with(buffer)
+	{
+		Ok(Header::Incomplete)
+	} else {
+		Ok(Header::Missing)
+	}
+}
+
+/// Parses a human readable header, such as
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn parse_v1(buffer: &[u8]) -> IoResult<Header> {
+	let Some(end) = buffer.windows(2).position(|window| window == b"\r\n")
+	else {
+		return if buffer.len() < V1_MAX_LENGTH {
+			Ok(Header::Incomplete)
+		} else {
+			Err(invalid_header("v1 header is too long"))
+		};
+	};
+	if end + 2 > V1_MAX_LENGTH {
+		return Err(invalid_header("v1 header is too long"));
+	}
+
+	let line = std::str::from_utf8(&buffer[..end])
+		.map_err(|_| invalid_header("v1 header isn't valid ASCII"))?;
+	let fields = line.split(' ').collect::<Vec<_>>();
+	let source = match fields.as_slice() {
+		["PROXY", "UNKNOWN", ..] => None,
+		["PROXY", "TCP4" | "TCP6", source, _, port, _] => {
+			let address = IpAddr::from_str(source)
+				.map_err(|_| invalid_header("invalid v1 source address"))?;
+			let port = u16::from_str(port)
+				.map_err(|_| invalid_header("invalid v1 source port"))?;
+			Some(SocketAddr::new(address, port))
+		}
+		_ => return Err(invalid_header("malformed v1 header")),
+	};
+
+	Ok(Header::Parsed {
+		length: end + 2,
+		source,
+	})
+}
+
+/// Parses a binary header, which is made of the signature, the version and
+/// command, the address family, the length of the rest of the header and
+/// then the addresses followed by optional TLVs
+fn parse_v2(buffer: &[u8]) -> IoResult<Header> {
+	if buffer.len() < V2_HEADER_LENGTH {
+		return Ok(Header::Incomplete);
+	}
+	let version_command = buffer[12];
+	let family = buffer[13];
+	let length = V2_HEADER_LENGTH +
+		u16::from_be_bytes([buffer[14], buffer[15]]) as usize;
+
+	if version_command >> 4 != 2 {
+		return Err(invalid_header("unsupported version"));
+	}
+	if buffer.len() < length {
+		return Ok(Header::Incomplete);
+	}
+	let addresses = &buffer[V2_HEADER_LENGTH..length];
+
+	let source = match (version_command & 0x0F, family >> 4) {
+		// LOCAL connections are made by the proxy itself
+		(0x0, _) => None,
+		// IPv4
+		(0x1, 0x1) if addresses.len() >= 12 => {
+			let address = Ipv4Addr::new(
+				addresses[0],
+				addresses[1],
+				addresses[2],
+				addresses[3],
+			);
+			let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+			Some(SocketAddr::new(IpAddr::V4(address), port))
+		}
+		// IPv6
+		(0x1, 0x2) if addresses.len() >= 36 => {
+			let mut octets = [0; 16];
+			octets.copy_from_slice(&addresses[..16]);
+			let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+			Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+		}
+		// Unix sockets and unspecified families don't carry an IP address
+		(0x1, 0x0 | 0x3) => None,
+		_ => return Err(invalid_header("malformed v2 header")),
+	};
+
+	Ok(Header::Parsed { length, source })
+}
+
+fn invalid_header(message: &str) -> IoError {
+	IoError::new(
+		ErrorKind::InvalidData,
+		format!("invalid PROXY protocol header: {}", message),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::AsyncWriteExt;
+
+	use super::*;
+
+	fn parse(buffer: &[u8]) -> (usize, Option<SocketAddr>) {
+		match parse_header(buffer).unwrap() {
+			Header::Parsed { length, source } => (length, source),
+			Header::Incomplete => panic!("header is incomplete"),
+			Header::Missing => panic!("header is missing"),
+		}
+	}
+
+	fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+		let mut header = V2_SIGNATURE.to_vec();
+		header.push(0x20 | command);
+		header.push(family);
+		header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+		header.extend_from_slice(addresses);
+		header
+	}
+
+	#[test]
+	fn v1_headers_are_parsed() {
+		let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+		assert_eq!(
+			parse(header),
+			(45, Some("192.0.2.1:56324".parse().unwrap()))
+		);
+		assert_eq!(
+			parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n"),
+			(46, Some("[2001:db8::1]:56324".parse().unwrap()))
+		);
+		assert_eq!(parse(b"PROXY UNKNOWN\r\n"), (15, None));
+	}
+
+	#[test]
+	fn invalid_v1_headers_are_rejected() {
+		assert!(matches!(
+			parse_header(b"PROXY TCP4 192.0.2.1"),
+			Ok(Header::Incomplete)
+		));
+		assert!(matches!(parse_header(b"PRO"), Ok(Header::Incomplete)));
+		assert!(matches!(
+			parse_header(b"GET / HTTP/1.1"),
+			Ok(Header::Missing)
+		));
+
+		assert!(parse_header(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n")
+			.is_err());
+		assert!(parse_header(b"PROXY TCP4 nope 198.51.100.1 56324 443\r\n")
+			.is_err());
+		assert!(parse_header(
+			b"PROXY TCP4 192.0.2.1 198.51.100.1 99999 443\r\n"
+		)
+		.is_err());
+		let too_long = [b"PROXY ".as_slice(), &[b'a'; V1_MAX_LENGTH]].concat();
+		assert!(parse_header(&too_long).is_err());
+	}
+
+	#[test]
+	fn v2_headers_are_parsed() {
+		let mut addresses = vec![192, 0, 2, 1, 198, 51, 100, 1];
+		addresses.extend_from_slice(&56324u16.to_be_bytes());
+		addresses.extend_from_slice(&443u16.to_be_bytes());
+		// A TLV after the addresses is skipped along with the header
+		addresses.extend_from_slice(&[0x04, 0x00, 0x01, 0xFF]);
+		let header = v2_header(0x1, 0x11, &addresses);
+		assert_eq!(
+			parse(&header),
+			(32, Some("192.0.2.1:56324".parse().unwrap()))
+		);
+
+		let mut addresses =
+			"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+		addresses.extend_from_slice(&[0; 16]);
+		addresses.extend_from_slice(&56324u16.to_be_bytes());
+		addresses.extend_from_slice(&443u16.to_be_bytes());
+		let header = v2_header(0x1, 0x21, &addresses);
+		assert_eq!(
+			parse(&header),
+			(52, Some("[2001:db8::1]:56324".parse().unwrap()))
+		);
+
+		// Health checks made by the proxy itself
+		assert_eq!(parse(&v2_header(0x0, 0x00, &[])), (16, None));
+	}
+
+	#[test]
+	fn invalid_v2_headers_are_rejected() {
+		let header = v2_header(0x1, 0x11, &[192, 0, 2, 1]);
+		assert!(parse_header(&header).is_err());
+		assert!(matches!(
+			parse_header(&header[..14]),
+			Ok(Header::Incomplete)
+		));
+
+		let mut header = v2_header(0x1, 0x11, &[0; 12]);
+		header.truncate(20);
+		assert!(matches!(parse_header(&header), Ok(Header::Incomplete)));
+
+		let mut header = v2_header(0x0, 0x00, &[]);
+		header[12] = 0x10;
+		assert!(parse_header(&header).is_err());
+	}
+
+	async fn connect(data: &'static [u8]) -> (TcpStream, SocketAddr) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			let mut client = TcpStream::connect(address).await.unwrap();
+			client.write_all(data).await.unwrap();
+		});
+		listener.accept().await.unwrap()
+	}
+
+	#[tokio::test]
+	async fn bytes_after_the_header_are_replayed() {
+		let (stream, peer) = connect(
+			b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1",
+		)
+		.await;
+		let mut stream = read_header(stream, peer, ProxyProtocol::Required)
+			.await
+			.unwrap();
+		assert_eq!(stream.remote_addr(), "192.0.2.1:56324".parse().unwrap());
+
+		let mut rest = String::new();
+		stream.read_to_string(&mut rest).await.unwrap();
+		assert_eq!(rest, "GET / HTTP/1.1");
+	}
+
+	#[tokio::test]
+	async fn missing_headers_depend_on_the_setting() {
+		let (stream, peer) = connect(b"GET / HTTP/1.1").await;
+		let mut stream = read_header(stream, peer, ProxyProtocol::Optional)
+			.await
+			.unwrap();
+		assert_eq!(stream.remote_addr(), peer);
+		let mut rest = String::new();
+		stream.read_to_string(&mut rest).await.unwrap();
+		assert_eq!(rest, "GET / HTTP/1.1");
+
+		let (stream, peer) = connect(b"GET / HTTP/1.1").await;
+		assert!(read_header(stream, peer, ProxyProtocol::Required)
+			.await
+			.is_err());
+	}
+}
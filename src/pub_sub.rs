@@ -0,0 +1,261 @@
+use std::{
+	collections::HashMap,
+	fmt::{Debug, Formatter, Result as FmtResult},
+	sync::{Arc, Mutex},
+};
+
+use futures::{stream, Stream};
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+
+pub const DEFAULT_TOPIC_CAPACITY: usize = 64;
+
+/// What happens to a subscriber that falls behind by more than the capacity
+/// of a topic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+	/// The oldest messages the subscriber hasn't received yet are skipped
+	DropOldest,
+	/// The subscriber is unsubscribed and its subscription ends
+	Disconnect,
+}
+
+/// An in-process hub that broadcasts messages published on a named topic to
+/// every subscriber of that topic. Cloning a `PubSub` gives another handle
+/// to the same hub.
+#[derive(Clone)]
+pub struct PubSub {
+	inner: Arc<PubSubInner>,
+}
+
+struct PubSubInner {
+	topics: Mutex<HashMap<String, Sender<String>>>,
+	capacity: usize,
+	backpressure: Backpressure,
+}
+
+impl PubSub {
+	pub fn new(capacity: usize, backpressure: Backpressure) -> Self {
+		PubSub {
+			inner: Arc::new(PubSubInner {
+				topics: Mutex::new(HashMap::new()),
+				capacity: capacity.max(1),
+				backpressure,
+			}),
+		}
+	}
+
+	pub fn get_capacity(&self) -> usize {
+		self.inner.capacity
+	}
+
+	pub fn get_backpressure(&self) -> Backpressure {
+		self.inner.backpressure
+	}
+
+	/// Publishes a message on a topic and returns the number of subscribers
+	/// it was sent to. Messages published on a topic without subscribers are
+	/// dropped.
+	pub fn publish(&self, topic: &str, message: &str) -> usize {
+		let topics = self.inner.topics.lock().unwrap();
+		topics
+			.get(topic)
+			.and_then(|sender| sender.send(message.to_string()).ok())
+			.unwrap_or(0)
+	}
+
+	/// Subscribes to a topic. The subscription lasts until it is dropped, so
+	/// holding it for as long as a connection is open ties the subscription
+	/// to the connection.
+	pub fn subscribe(&self, topic: &str) -> Subscription {
+		let mut topics = self.inner.topics.lock().unwrap();
+		let receiver = topics
+			.entry(topic.to_string())
+			.or_insert_with(|| broadcast::channel(self.inner.capacity).0)
+			.subscribe();
+		Subscription {
+			topic: topic.to_string(),
+			receiver: Some(receiver),
+			pub_sub: self.inner.clone(),
+		}
+	}
+
+	pub fn get_subscriber_count(&self, topic: &str) -> usize {
+		let topics = self.inner.topics.lock().unwrap();
+		topics
+			.get(topic)
+			.map(|sender| sender.receiver_count())
+			.unwrap_or(0)
+	}
+
+	/// Returns the topics that currently have subscribers
+	pub fn get_topics(&self) -> Vec<String> {
+		let topics = self.inner.topics.lock().unwrap();
+		topics.keys().cloned().collect()
+	}
+}
+
+impl Default for PubSub {
+	fn default() -> Self {
+		PubSub::new(DEFAULT_TOPIC_CAPACITY, Backpressure::DropOldest)
+	}
+}
+
+impl Debug for PubSub {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("PubSub")
+			.field("topics", &self.get_topics())
+			.field("capacity", &self.inner.capacity)
+			.field("backpressure", &self.inner.backpressure)
+			.finish()
+	}
+}
+
+/// A subscription to a topic of a [`PubSub`]. The subscriber is removed from
+/// the topic when this is dropped.
+pub struct Subscription {
+	topic: String,
+	// Taken once the subscription ends, so that a disconnected subscriber
+	// isn't counted and doesn't keep the topic alive
+	receiver: Option<Receiver<String>>,
+	pub_sub: Arc<PubSubInner>,
+}
+
+impl Subscription {
+	pub fn get_topic(&self) -> &str {
+		&self.topic
+	}
+
+	/// Waits for the next message on the topic. Returns `None` if the
+	/// subscription has ended, either because the hub was dropped or because
+	/// the subscriber fell behind with the [`Backpressure::Disconnect`]
+	/// policy.
+	pub async fn recv(&mut self) -> Option<String> {
+		loop {
+			match self.receiver.as_mut()?.recv().await {
+				Ok(message) => return Some(message),
+				Err(RecvError::Lagged(skipped)) => {
+					if self.pub_sub.backpressure == Backpressure::Disconnect {
+						log::warn!(
+							"Subscriber to `{}` fell behind by {} messages and was disconnected",
+							self.topic,
+							skipped
+						);
+						self.close();
+						return None;
+					}
+				}
+				Err(RecvError::Closed) => {
+					self.close();
+					return None;
+				}
+			}
+		}
+	}
+
+	/// Removes the subscriber from the topic, and the topic from the hub if
+	/// this was its last subscriber
+	fn close(&mut self) {
+		if let Ok(mut topics) = self.pub_sub.topics.lock() {
+			if self.receiver.take().is_none() {
+				return;
+			}
+			let is_empty = topics
+				.get(&self.topic)
+				.map(|sender| sender.receiver_count() == 0)
+				.unwrap_or(false);
+			if is_empty {
+				topics.remove(&self.topic);
+			}
+		}
+	}
+
+	pub fn unsubscribe(self) {
+		drop(self);
+	}
+
+	pub fn into_stream(self) -> impl Stream<Item = String> {
+		stream::unfold(self, |mut subscription| async move {
+			let message = subscription.recv().await?;
+			Some((message, subscription))
+		})
+	}
+}
+
+impl Debug for Subscription {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("Subscription")
+			.field("topic", &self.topic)
+			.field("closed", &self.receiver.is_none())
+			.finish()
+	}
+}
+
+impl Drop for Subscription {
+	fn drop(&mut self) {
+		self.close();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn messages_are_sent_to_every_subscriber() {
+		let pub_sub = PubSub::default();
+		let mut first = pub_sub.subscribe("topic");
+		let mut second = pub_sub.subscribe("topic");
+		let mut other = pub_sub.subscribe("other");
+
+		assert_eq!(pub_sub.publish("topic", "message"), 2);
+		assert_eq!(first.recv().await.as_deref(), Some("message"));
+		assert_eq!(second.recv().await.as_deref(), Some("message"));
+
+		pub_sub.publish("other", "other message");
+		assert_eq!(other.recv().await.as_deref(), Some("other message"));
+	}
+
+	#[test]
+	fn dropping_the_last_subscription_removes_the_topic() {
+		let pub_sub = PubSub::default();
+		let first = pub_sub.subscribe("topic");
+		let second = pub_sub.subscribe("topic");
+		assert_eq!(pub_sub.get_subscriber_count("topic"), 2);
+
+		drop(first);
+		assert_eq!(pub_sub.get_subscriber_count("topic"), 1);
+		assert_eq!(pub_sub.get_topics(), vec!["topic".to_string()]);
+
+		second.unsubscribe();
+		assert_eq!(pub_sub.get_subscriber_count("topic"), 0);
+		assert!(pub_sub.get_topics().is_empty());
+		assert_eq!(pub_sub.publish("topic", "message"), 0);
+	}
+
+	#[tokio::test]
+	async fn lagging_subscribers_skip_the_oldest_messages() {
+		let pub_sub = PubSub::new(2, Backpressure::DropOldest);
+		let mut subscription = pub_sub.subscribe("topic");
+		for message in ["1", "2", "3", "4"] {
+			pub_sub.publish("topic", message);
+		}
+
+		assert_eq!(subscription.recv().await.as_deref(), Some("3"));
+		assert_eq!(subscription.recv().await.as_deref(), Some("4"));
+		assert_eq!(pub_sub.get_subscriber_count("topic"), 1);
+	}
+
+	#[tokio::test]
+	async fn lagging_subscribers_can_be_disconnected() {
+		let pub_sub = PubSub::new(2, Backpressure::Disconnect);
+		let mut subscription = pub_sub.subscribe("topic");
+		for message in ["1", "2", "3", "4"] {
+			pub_sub.publish("topic", message);
+		}
+
+		assert_eq!(subscription.recv().await, None);
+		assert_eq!(subscription.recv().await, None);
+		assert_eq!(pub_sub.get_subscriber_count("topic"), 0);
+		assert!(pub_sub.get_topics().is_empty());
+	}
+}
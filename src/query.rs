@@ -0,0 +1,569 @@
+use std::{
+	error::Error as StdError,
+	fmt::{Debug, Display, Formatter, Result as FmtResult},
+	iter,
+	mem,
+};
+
+use serde::{
+	de::{
+		value::{Error as DeError, MapDeserializer, SeqDeserializer},
+		DeserializeOwned,
+		Error as _,
+		IntoDeserializer,
+		Unexpected,
+		Visitor,
+	},
+	forward_to_deserialize_any,
+	Deserializer,
+};
+use serde_path_to_error::Segment;
+
+// Keys nested deeper than this are kept whole, so that a key with thousands
+// of brackets can't nest values deep enough to overflow the stack
+const MAX_KEY_DEPTH: usize = 32;
+
+/// The parameters of a query string. Repeated keys keep every value, in the
+/// order they appear in the query string.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct QueryMap {
+	entries: Vec<(String, Vec<String>)>,
+}
+
+impl QueryMap {
+	pub fn new() -> Self {
+		QueryMap::default()
+	}
+
+	/// Parses a query string, such as `tag=a&tag=b&page=2`. Invalid query
+	/// strings are parsed as empty.
+	pub(crate) fn parse(query: &str) -> Self {
+		let mut map = QueryMap::new();
+		let pairs = serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+			.unwrap_or_default();
+		for (key, value) in pairs {
+			map.append(&key, &value);
+		}
+		map
+	}
+
+	/// Returns the first value of a parameter
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.get_all(key).first().map(String::as_str)
+	}
+
+	/// Returns every value of a parameter, such as both values of
+	/// `?tag=a&tag=b`
+	pub fn get_all(&self, key: &str) -> &[String] {
+		self.entries
+			.iter()
+			.find(|(name, _)| name == key)
+			.map(|(_, values)| values.as_slice())
+			.unwrap_or_default()
+	}
+
+	pub fn contains(&self, key: &str) -> bool {
+		self.entries.iter().any(|(name, _)| name == key)
+	}
+
+	pub fn append(&mut self, key: &str, value: &str) {
+		match self.entries.iter_mut().find(|(name, _)| name == key) {
+			Some((_, values)) => values.push(value.to_string()),
+			None => self
+				.entries
+				.push((key.to_string(), vec![value.to_string()])),
+		}
+	}
+
+	/// Iterates over the parameters and their values, in the order they
+	/// first appear in the query string
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+		self.entries
+			.iter()
+			.map(|(name, values)| (name.as_str(), values.as_slice()))
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Deserializes the parameters into a type. Repeated keys, `tag[]=a`
+	/// and `tag[0]=a` are read as sequences, and `filter[min]=1` is read as
+	/// a nested map. Numbers and booleans are parsed from their text.
+	pub fn deserialize<TQuery>(&self) -> Result<TQuery, QueryError>
+	where
+		TQuery: DeserializeOwned,
+	{
+		let mut root = vec![];
+		for (key, values) in &self.entries {
+			let segments = split_key(key);
+			for value in values {
+				insert(&mut root, &segments, value);
+			}
+		}
+
+		serde_path_to_error::deserialize(QueryValue::Map(root)).map_err(|err| {
+			QueryError {
+				field: format_path(err.path()),
+				message: err.inner().to_string(),
+			}
+		})
+	}
+}
+
+impl Debug for QueryMap {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+/// An error that occurs while deserializing the query string into a type.
+/// When returned from a middleware using `?`, it is converted into an
+/// [`Error`](crate::Error) with a 400 status, and a body describing the
+/// invalid field.
+#[derive(Debug)]
+pub struct QueryError {
+	field: Option<String>,
+	message: String,
+}
+
+impl QueryError {
+	/// Returns the parameter that's invalid, such as `tags[1]`, if the error
+	/// is about a single parameter
+	pub fn get_field(&self) -> Option<&str> {
+		self.field.as_deref()
+	}
+
+	pub fn get_message(&self) -> &str {
+		&self.message
+	}
+
+	pub fn get_status(&self) -> u16 {
+		400
+	}
+}
+
+impl Display for QueryError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		match &self.field {
+			Some(field) => write!(
+				f,
+				"invalid query parameter `{}`: {}",
+				field, self.message
+			),
+			None => write!(f, "invalid query string: {}", self.message),
+		}
+	}
+}
+
+impl StdError for QueryError {}
+
+/// The parameters arranged by their bracket notation, so that they can be
+/// deserialized into nested types
+enum QueryValue {
+	String(String),
+	Seq(Vec<QueryValue>),
+	Map(Vec<(String, QueryValue)>),
+}
+
+/// Splits a key such as `filter[price][min]` into its parts. Keys with
+/// unbalanced brackets, or more than [`MAX_KEY_DEPTH`] of them, are kept
+/// whole.
+fn split_key(key: &str) -> Vec<&str> {
+	let Some(start) = key.find('[').filter(|start| *start > 0) else {
+		return vec![key];
+	};
+	let mut segments = vec![&key[..start]];
+	let mut rest = &key[start..];
+	while let Some(inner) = rest.strip_prefix('[') {
+		let Some(end) = inner.find(']') else {
+			return vec![key];
+		};
+		if segments.len() > MAX_KEY_DEPTH {
+			return vec![key];
+		}
+		segments.push(&inner[..end]);
+		rest = &inner[end + 1..];
+	}
+	if !rest.is_empty() {
+		return vec![key];
+	}
+	segments
+}
+
+/// Inserts a value at the path of a split key, creating the sequences and
+/// maps along the way
+fn insert(
+	mut entries: &mut Vec<(String, QueryValue)>,
+	mut segments: &[&str],
+	value: &str,
+) {
+	while let [key, rest @ ..] = segments {
+		let index = match entries.iter().position(|(name, _)| name == key) {
+			Some(index) => index,
+			None => {
+				let node = match rest {
+					[] => QueryValue::String(value.to_string()),
+					[""] => QueryValue::Seq(vec![]),
+					_ => QueryValue::Map(vec![]),
+				};
+				entries.push((key.to_string(), node));
+				if rest.is_empty() {
+					return;
+				}
+				entries.len() - 1
+			}
+		};
+
+		let node = &mut entries[index].1;
+		match (rest, node) {
+			// `tag=a&tag=b` and `tag[]=a&tag[]=b` both append to a sequence
+			([] | [""], node @ QueryValue::String(_)) => {
+				let first = mem::replace(node, QueryValue::Seq(vec![]));
+				*node = QueryValue::Seq(vec![
+					first,
+					QueryValue::String(value.to_string()),
+				]);
+				return;
+			}
+			([] | [""], QueryValue::Seq(values)) => {
+				values.push(QueryValue::String(value.to_string()));
+				return;
+			}
+			([_, ..], QueryValue::Map(children)) => {
+				entries = children;
+				segments = rest;
+			}
+			// Conflicting parameters, such as `a=1&a[b]=2`, keep the first form
+			_ => return,
+		}
+	}
+}
+
+fn format_path(path: &serde_path_to_error::Path) -> Option<String> {
+	let mut field = String::new();
+	for segment in path.iter() {
+		let part = match segment {
+			Segment::Seq { index } => index.to_string(),
+			Segment::Map { key } => key.clone(),
+			Segment::Enum { variant } => variant.clone(),
+			Segment::Unknown => "?".to_string(),
+		};
+		if field.is_empty() {
+			field = part;
+		} else {
+			field.push_str(&format!("[{}]", part));
+		}
+	}
+	if field.is_empty() {
+		None
+	} else {
+		Some(field)
+	}
+}
+
+impl QueryValue {
+	fn unexpected(&self) -> Unexpected<'_> {
+		match self {
+			QueryValue::String(value) => Unexpected::Str(value),
+			QueryValue::Seq(_) => Unexpected::Seq,
+			QueryValue::Map(_) => Unexpected::Map,
+		}
+	}
+
+	/// Returns the values of a map with only numeric keys, such as
+	/// `tag[0]=a&tag[1]=b`, ordered by their keys
+	fn into_indexed(entries: Vec<(String, QueryValue)>) -> Option<Vec<Self>> {
+		let mut indexed = entries
+			.into_iter()
+			.map(|(key, value)| Some((key.parse::<usize>().ok()?, value)))
+			.collect::<Option<Vec<_>>>()?;
+		indexed.sort_by_key(|(index, _)| *index);
+		Some(indexed.into_iter().map(|(_, value)| value).collect())
+	}
+}
+
+macro_rules! deserialize_parsed {
+	($($method:ident => $visit:ident,)*) => {
+		$(
+			fn $method<TVisitor>(
+				self,
+				visitor: TVisitor,
+			) -> Result<TVisitor::Value, DeError>
+			where
+				TVisitor: Visitor<'de>,
+			{
+				match self {
+					QueryValue::String(value) => match value.trim().parse() {
+						Ok(parsed) => visitor.$visit(parsed),
+						Err(_) => Err(DeError::invalid_value(
+							Unexpected::Str(&value),
+							&visitor,
+						)),
+					},
+					value => {
+						Err(DeError::invalid_type(value.unexpected(), &visitor))
+					}
+				}
+			}
+		)*
+	};
+}
+
+impl<'de> Deserializer<'de> for QueryValue {
+	type Error = DeError;
+
+	deserialize_parsed! {
+		deserialize_i8 => visit_i8,
+		deserialize_i16 => visit_i16,
+		deserialize_i32 => visit_i32,
+		deserialize_i64 => visit_i64,
+		deserialize_i128 => visit_i128,
+		deserialize_u8 => visit_u8,
+		deserialize_u16 => visit_u16,
+		deserialize_u32 => visit_u32,
+		deserialize_u64 => visit_u64,
+		deserialize_u128 => visit_u128,
+		deserialize_f32 => visit_f32,
+		deserialize_f64 => visit_f64,
+	}
+
+	fn deserialize_any<TVisitor>(
+		self,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		match self {
+			QueryValue::String(value) => visitor.visit_string(value),
+			QueryValue::Seq(values) => {
+				visitor.visit_seq(SeqDeserializer::new(values.into_iter()))
+			}
+			QueryValue::Map(entries) => {
+				visitor.visit_map(MapDeserializer::new(entries.into_iter()))
+			}
+		}
+	}
+
+	fn deserialize_bool<TVisitor>(
+		self,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		match self {
+			QueryValue::String(value) => {
+				match value.trim().to_lowercase().as_str() {
+					"true" | "1" | "on" => visitor.visit_bool(true),
+					"false" | "0" | "off" => visitor.visit_bool(false),
+					_ => Err(DeError::invalid_value(
+						Unexpected::Str(&value),
+						&visitor,
+					)),
+				}
+			}
+			value => Err(DeError::invalid_type(value.unexpected(), &visitor)),
+		}
+	}
+
+	/// An empty value, such as `?page=`, is treated as missing
+	fn deserialize_option<TVisitor>(
+		self,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		match self {
+			QueryValue::String(value) if value.is_empty() => {
+				visitor.visit_none()
+			}
+			value => visitor.visit_some(value),
+		}
+	}
+
+	fn deserialize_unit<TVisitor>(
+		self,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		visitor.visit_unit()
+	}
+
+	fn deserialize_newtype_struct<TVisitor>(
+		self,
+		_name: &'static str,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		visitor.visit_newtype_struct(self)
+	}
+
+	/// A single value is read as a sequence of one, so that `?tag=a` can be
+	/// deserialized into a `Vec`
+	fn deserialize_seq<TVisitor>(
+		self,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		match self {
+			QueryValue::String(value) => visitor.visit_seq(
+				SeqDeserializer::new(iter::once(QueryValue::String(value))),
+			),
+			QueryValue::Seq(values) => {
+				visitor.visit_seq(SeqDeserializer::new(values.into_iter()))
+			}
+			QueryValue::Map(entries) => match QueryValue::into_indexed(entries)
+			{
+				Some(values) => {
+					visitor.visit_seq(SeqDeserializer::new(values.into_iter()))
+				}
+				None => Err(DeError::invalid_type(Unexpected::Map, &visitor)),
+			},
+		}
+	}
+
+	fn deserialize_tuple<TVisitor>(
+		self,
+		_len: usize,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		self.deserialize_seq(visitor)
+	}
+
+	fn deserialize_tuple_struct<TVisitor>(
+		self,
+		_name: &'static str,
+		_len: usize,
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		self.deserialize_seq(visitor)
+	}
+
+	fn deserialize_enum<TVisitor>(
+		self,
+		_name: &'static str,
+		_variants: &'static [&'static str],
+		visitor: TVisitor,
+	) -> Result<TVisitor::Value, DeError>
+	where
+		TVisitor: Visitor<'de>,
+	{
+		match self {
+			QueryValue::String(value) => {
+				visitor.visit_enum(value.into_deserializer())
+			}
+			value => Err(DeError::invalid_type(value.unexpected(), &visitor)),
+		}
+	}
+
+	forward_to_deserialize_any! {
+		char str string bytes byte_buf unit_struct map struct identifier
+		ignored_any
+	}
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for QueryValue {
+	type Deserializer = Self;
+
+	fn into_deserializer(self) -> Self {
+		self
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use serde_json::{json, Value};
+
+	use super::*;
+	use crate::Error;
+
+	fn parse(query: &str) -> Value {
+		QueryMap::parse(query).deserialize().unwrap()
+	}
+
+	#[test]
+	fn repeated_keys_are_sequences() {
+		let query = QueryMap::parse("tag=a&page=2&tag=b");
+		assert_eq!(query.get("tag"), Some("a"));
+		assert_eq!(query.get_all("tag"), ["a", "b"]);
+		assert_eq!(
+			parse("tag=a&page=2&tag=b"),
+			json!({ "tag": ["a", "b"], "page": "2" })
+		);
+	}
+
+	#[test]
+	fn bracket_keys_are_sequences() {
+		assert_eq!(parse("tag[]=a&tag[]=b"), json!({ "tag": ["a", "b"] }));
+
+		let tags = QueryMap::parse("tag[1]=b&tag[0]=a")
+			.deserialize::<HashMap<String, Vec<String>>>()
+			.unwrap();
+		assert_eq!(tags["tag"], ["a", "b"]);
+
+		// A single value can still be read as a sequence
+		let tags = QueryMap::parse("tag=a")
+			.deserialize::<HashMap<String, Vec<String>>>()
+			.unwrap();
+		assert_eq!(tags["tag"], ["a"]);
+	}
+
+	#[test]
+	fn nested_keys_are_maps() {
+		assert_eq!(
+			parse("filter[price][min]=1&filter[price][max]=10&filter[name]=x"),
+			json!({
+				"filter": {
+					"price": { "min": "1", "max": "10" },
+					"name": "x",
+				},
+			})
+		);
+		// Conflicting parameters keep the first form
+		assert_eq!(parse("a=1&a[b]=2"), json!({ "a": "1" }));
+	}
+
+	#[test]
+	fn invalid_fields_are_bad_requests() {
+		let err = QueryMap::parse("tag=1&tag=x")
+			.deserialize::<HashMap<String, Vec<u32>>>()
+			.unwrap_err();
+		assert_eq!(err.get_field(), Some("tag[1]"));
+
+		let error: Error<()> = err.into();
+		assert_eq!(error.get_status(), Some(400));
+		let body = String::from_utf8_lossy(error.get_body_bytes().unwrap());
+		assert!(body.starts_with("invalid query parameter `tag[1]`"));
+	}
+
+	#[test]
+	fn deeply_nested_keys_are_kept_whole() {
+		let key = format!("a{}", "[b]".repeat(MAX_KEY_DEPTH));
+		assert_eq!(split_key(&key).len(), MAX_KEY_DEPTH + 1);
+
+		let key = format!("a{}", "[b]".repeat(100_000));
+		assert_eq!(split_key(&key), [key.as_str()]);
+		assert_eq!(parse(&format!("{}=1", key))[&key], "1");
+	}
+}
@@ -2,13 +2,17 @@ use std::sync::Arc;
 
 use handlebars::{Handlebars, RenderError};
 use serde::Serialize;
+use serde_json::Value;
 
 use crate::Context;
 
 pub trait RenderEngine: Context {
-	fn get_register(&self) -> &Arc<Handlebars>;
+	fn get_register(&self) -> &Arc<Handlebars<'_>>;
 	fn set_register(&mut self, register: Arc<Handlebars<'static>>);
 
+	/// Renders a template with the given data. The response's locals are
+	/// available to the template too, unless the data has a field with the
+	/// same name.
 	fn render<TParams>(
 		&mut self,
 		template_name: &str,
@@ -17,7 +21,19 @@ pub trait RenderEngine: Context {
 	where
 		TParams: Serialize,
 	{
-		let rendered = self.get_register().render(template_name, data)?;
+		let data = serde_json::to_value(data)?;
+		let data = match data {
+			Value::Object(data) => {
+				let mut merged = self.get_response().get_locals().clone();
+				merged.extend(data);
+				Value::Object(merged)
+			}
+			Value::Null => {
+				Value::Object(self.get_response().get_locals().clone())
+			}
+			data => data,
+		};
+		let rendered = self.get_register().render(template_name, &data)?;
 		self.content_type("text/html").body(&rendered);
 		Ok(self)
 	}
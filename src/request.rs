@@ -1,84 +1,210 @@
 use std::{
 	collections::HashMap,
-	fmt::{Debug, Formatter, Result as FmtResult},
+	error::Error as StdError,
+	fmt::{Debug, Display, Formatter, Result as FmtResult},
+	mem,
 	net::{IpAddr, SocketAddr},
 	str::{self, Utf8Error},
+	sync::Arc,
 };
 
-use hyper::{body, Body, Request as HyperRequestInternal, Uri, Version};
-
-use crate::{cookie::Cookie, HttpMethod};
+use futures::stream;
+use hyper::{
+	body::{self, Bytes, HttpBody},
+	http::Extensions,
+	upgrade::OnUpgrade,
+	Body,
+	Error as HyperError,
+	Request as HyperRequestInternal,
+	Uri,
+	Version,
+};
+use serde::de::DeserializeOwned;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "tracing")]
+use crate::TraceContext;
+use crate::{
+	cookie::Cookie,
+	headers::{Header, HeaderMap},
+	negotiation,
+	pub_sub::PubSub,
+	query::{QueryError, QueryMap},
+	service::BoxError,
+	trusted_proxies::{self, ForwardedHeaders, ForwardedInfo, TrustedProxies},
+	HttpMethod,
+};
 
 pub type HyperRequest = HyperRequestInternal<Body>;
 
 pub struct Request {
 	pub(crate) socket_addr: SocketAddr,
-	pub(crate) body: Vec<u8>,
+	pub(crate) body: RequestBody,
 	pub(crate) method: HttpMethod,
 	pub(crate) uri: Uri,
 	pub(crate) version: (u8, u8),
-	pub(crate) headers: HashMap<String, Vec<String>>,
-	pub(crate) query: HashMap<String, String>,
+	pub(crate) headers: HeaderMap,
+	pub(crate) query: QueryMap,
 	pub(crate) params: HashMap<String, String>,
 	pub(crate) cookies: Vec<Cookie>,
+	pub(crate) pub_sub: PubSub,
+	pub(crate) forwarded: ForwardedInfo,
+	pub(crate) cancellation_token: CancellationToken,
+	pub(crate) body_limit: Option<usize>,
+	pub(crate) request_id: Option<String>,
+	pub(crate) route: Option<String>,
+	pub(crate) record_middleware_timings: bool,
+	pub(crate) extensions: Extensions,
+	#[cfg(feature = "tracing")]
+	pub(crate) trace_context: TraceContext,
 	pub(crate) hyper_request: HyperRequest,
 }
 
-impl Request {
-	pub async fn from_hyper(
-		socket_addr: SocketAddr,
-		req: HyperRequest,
-	) -> Self {
-		let (parts, hyper_body) = req.into_parts();
-		let mut headers = HashMap::<String, Vec<String>>::new();
-		parts.headers.iter().for_each(|(key, value)| {
-			let key = key.to_string();
-			let value = value.to_str();
-
-			if value.is_err() {
-				return;
-			}
-			let value = value.unwrap().to_string();
+/// The state of the request body. The body stays inside the hyper request
+/// until it's either buffered or taken as a stream.
+#[derive(Debug)]
+pub(crate) enum RequestBody {
+	Pending,
+	Buffered(Bytes),
+	Streamed,
+	/// Reading the body failed, so the same error is returned every time
+	Failed(BodyError),
+}
 
-			if let Some(values) = headers.get_mut(&key) {
-				values.push(value);
-			} else {
-				headers.insert(key.to_string(), vec![value]);
-			}
-		});
-		let body = body::to_bytes(hyper_body).await.unwrap().to_vec();
-		Request {
+impl Request {
+	pub fn from_hyper(socket_addr: SocketAddr, req: HyperRequest) -> Self {
+		#[cfg(feature = "tracing")]
+		let trace_context = TraceContext::from_hyper(&req);
+		let mut request = Request {
 			socket_addr,
-			body: body.clone(),
-			method: HttpMethod::from(parts.method.clone()),
-			uri: parts.uri.clone(),
-			version: match parts.version {
-				Version::HTTP_09 => (0, 9),
-				Version::HTTP_10 => (1, 0),
-				Version::HTTP_11 => (1, 1),
-				Version::HTTP_2 => (2, 0),
-				Version::HTTP_3 => (3, 0),
-				_ => (0, 0),
-			},
-			headers: headers.clone(),
-			query: if let Some(query) = parts.uri.query() {
-				serde_urlencoded::from_str(query)
-					.unwrap_or_else(|_| HashMap::new())
-			} else {
-				HashMap::new()
-			},
+			body: RequestBody::Pending,
+			method: HttpMethod::Get,
+			uri: Uri::default(),
+			version: (0, 0),
+			headers: HeaderMap::new(),
+			query: QueryMap::new(),
 			params: HashMap::new(),
 			cookies: vec![],
-			hyper_request: HyperRequest::from_parts(parts, Body::from(body)),
+			pub_sub: PubSub::default(),
+			forwarded: ForwardedInfo::default(),
+			cancellation_token: CancellationToken::new(),
+			body_limit: None,
+			request_id: None,
+			route: None,
+			record_middleware_timings: false,
+			extensions: Extensions::new(),
+			#[cfg(feature = "tracing")]
+			trace_context,
+			hyper_request: HyperRequest::default(),
+		};
+		request.set_hyper_request(req);
+		request
+	}
+
+	/// Replaces the underlying hyper request, updating everything that's
+	/// parsed from it. The body is read from the new request.
+	pub(crate) fn set_hyper_request(&mut self, req: HyperRequest) {
+		let mut headers = HeaderMap::new();
+		req.headers().iter().for_each(|(key, value)| {
+			if let Ok(value) = value.to_str() {
+				headers.append(key.as_str(), value);
+			}
+		});
+		self.body = RequestBody::Pending;
+		self.method = HttpMethod::from(req.method().clone());
+		self.uri = req.uri().clone();
+		self.version = match req.version() {
+			Version::HTTP_09 => (0, 9),
+			Version::HTTP_10 => (1, 0),
+			Version::HTTP_11 => (1, 1),
+			Version::HTTP_2 => (2, 0),
+			Version::HTTP_3 => (3, 0),
+			_ => (0, 0),
+		};
+		self.headers = headers;
+		self.query = QueryMap::parse(req.uri().query().unwrap_or_default());
+		self.hyper_request = req;
+	}
+
+	/// Takes the underlying hyper request along with its body and extensions,
+	/// so that it can be handed to another service. The body can't be read
+	/// from this request afterwards, unless it was already buffered.
+	pub(crate) fn take_hyper_request(
+		&mut self,
+	) -> Result<HyperRequest, BodyError> {
+		let body = self.take_body_stream()?;
+		let mut req = HyperRequest::new(body);
+		*req.method_mut() = self.hyper_request.method().clone();
+		*req.uri_mut() = self.hyper_request.uri().clone();
+		*req.version_mut() = self.hyper_request.version();
+		*req.headers_mut() = self.hyper_request.headers().clone();
+		*req.extensions_mut() = mem::take(self.hyper_request.extensions_mut());
+		Ok(req)
+	}
+
+	/// Reads the entire body into memory, if it hasn't been read already,
+	/// and returns it. Subsequent calls return the cached body.
+	pub async fn get_body_bytes(&mut self) -> Result<&[u8], BodyError> {
+		if let RequestBody::Pending = self.body {
+			// Whatever happens, the body can't be read from the connection
+			// again after this
+			self.body = RequestBody::Streamed;
+			let body = mem::take(self.hyper_request.body_mut());
+			let result = match self.body_limit {
+				Some(limit) => read_limited(body, limit).await,
+				None => body::to_bytes(body).await.map_err(get_read_error),
+			};
+			self.body = match result {
+				Ok(bytes) => RequestBody::Buffered(bytes),
+				Err(err) => RequestBody::Failed(err),
+			};
+		}
+		match &self.body {
+			RequestBody::Buffered(bytes) => Ok(bytes),
+			RequestBody::Failed(err) => Err(err.clone()),
+			_ => Err(BodyError::Consumed),
 		}
 	}
 
-	pub fn get_body_bytes(&self) -> &[u8] {
-		&self.body
+	pub async fn get_body(&mut self) -> Result<String, BodyError> {
+		let bytes = self.get_body_bytes().await?;
+		Ok(str::from_utf8(bytes)
+			.map_err(BodyError::InvalidUtf8)?
+			.to_string())
 	}
 
-	pub fn get_body(&self) -> Result<String, Utf8Error> {
-		Ok(str::from_utf8(&self.body)?.to_string())
+	/// Returns the body if it has already been read into memory, without
+	/// reading anything from the connection.
+	pub fn get_buffered_body(&self) -> Option<&[u8]> {
+		match &self.body {
+			RequestBody::Buffered(bytes) => Some(bytes),
+			_ => None,
+		}
+	}
+
+	/// Takes the body as a stream of chunks, so that it can be processed
+	/// without holding all of it in memory. Once taken, the body can't be read
+	/// again. If the body was already buffered, the buffered data is streamed
+	/// instead and remains available.
+	pub fn take_body_stream(&mut self) -> Result<Body, BodyError> {
+		match mem::replace(&mut self.body, RequestBody::Streamed) {
+			RequestBody::Pending => {
+				let body = mem::take(self.hyper_request.body_mut());
+				Ok(match self.body_limit {
+					Some(limit) => limit_body(body, limit),
+					None => body,
+				})
+			}
+			RequestBody::Buffered(bytes) => {
+				self.body = RequestBody::Buffered(bytes.clone());
+				Ok(Body::from(bytes))
+			}
+			RequestBody::Streamed => Err(BodyError::Consumed),
+			RequestBody::Failed(err) => {
+				self.body = RequestBody::Failed(err.clone());
+				Err(err)
+			}
+		}
 	}
 
 	pub fn get_method(&self) -> &HttpMethod {
@@ -86,18 +212,23 @@ impl Request {
 	}
 
 	pub fn get_length(&self) -> u128 {
-		if let Some(length) = self.headers.get("Content-Length") {
-			if let Some(value) = length.get(0) {
-				if let Ok(value) = value.parse::<u128>() {
-					return value;
-				}
+		if let Some(length) = self.get_header("Content-Length") {
+			if let Ok(value) = length.parse::<u128>() {
+				return value;
 			}
 		}
-		self.body.len() as u128
+		self.get_buffered_body()
+			.map(|body| body.len() as u128)
+			.unwrap_or(0)
 	}
 
 	pub fn get_path(&self) -> String {
-		self.uri.path().to_string()
+		// CONNECT requests only have an authority, so route them as /
+		if self.uri.path().is_empty() {
+			"/".to_string()
+		} else {
+			self.uri.path().to_string()
+		}
 	}
 
 	pub fn get_full_url(&self) -> String {
@@ -117,6 +248,9 @@ impl Request {
 	}
 
 	pub fn get_host(&self) -> String {
+		if let Some(host) = &self.forwarded.host {
+			return host.clone();
+		}
 		self.uri
 			.host()
 			.map(String::from)
@@ -124,15 +258,22 @@ impl Request {
 	}
 
 	pub fn get_host_and_port(&self) -> String {
-		format!(
-			"{}{}",
-			self.uri.host().unwrap(),
-			if let Some(port) = self.uri.port_u16() {
-				format!(":{}", port)
-			} else {
-				String::new()
-			}
-		)
+		if let Some(host) = &self.forwarded.host {
+			return host.clone();
+		}
+		if let Some(host) = self.uri.host() {
+			format!(
+				"{}{}",
+				host,
+				if let Some(port) = self.uri.port_u16() {
+					format!(":{}", port)
+				} else {
+					String::new()
+				}
+			)
+		} else {
+			self.get_header("host").unwrap_or_default()
+		}
 	}
 
 	pub fn get_content_type(&self) -> String {
@@ -153,7 +294,9 @@ impl Request {
 	}
 
 	pub fn get_protocol(&self) -> String {
-		// TODO support X-Forwarded-Proto
+		if let Some(protocol) = &self.forwarded.protocol {
+			return protocol.clone();
+		}
 		self.uri.scheme_str().unwrap_or("http").to_string()
 	}
 
@@ -161,17 +304,78 @@ impl Request {
 		self.get_protocol() == "https"
 	}
 
+	/// Returns the address of the client. If the app trusts the proxy the
+	/// request came through, this is the address the proxy reported.
 	pub fn get_ip(&self) -> IpAddr {
-		self.socket_addr.ip()
-	}
-
+		self.forwarded.ip.unwrap_or_else(|| self.socket_addr.ip())
+	}
+
+	/// Returns the address of the peer the request was received from, which
+	/// is the nearest proxy if the app is behind one
+	pub fn get_socket_addr(&self) -> SocketAddr {
+		self.socket_addr
+	}
+
+	/// Resolves the client information reported by the proxies that the app
+	/// trusts
+	pub(crate) fn apply_trusted_proxies(
+		&mut self,
+		trusted_proxies: &TrustedProxies,
+		forwarded_headers: ForwardedHeaders,
+	) {
+		self.forwarded = trusted_proxies::resolve(
+			trusted_proxies,
+			forwarded_headers,
+			self.socket_addr.ip(),
+			self.get_header("Forwarded"),
+			self.get_header("X-Forwarded-For"),
+			self.get_header("X-Forwarded-Proto"),
+			self.get_header("X-Forwarded-Host"),
+		);
+	}
+
+	/// Returns true if the content type of the request matches any of the
+	/// given types. Types can be full media types, wildcards such as
+	/// `text/*`, suffixes such as `+json` or extensions such as `json`.
 	pub fn is(&self, mimes: &[&str]) -> bool {
 		let given = self.get_content_type();
-		mimes.iter().any(|mime| mime == &given)
+		mimes.iter().any(|mime| negotiation::is_type(&given, mime))
+	}
+
+	/// Returns the type the client prefers out of the given types, based on
+	/// the `Accept` header, or `None` if it accepts none of them. Types can be
+	/// full media types or extensions such as `json`, and the matching one is
+	/// returned as it was given.
+	pub fn accepts(&self, types: &[&str]) -> Option<String> {
+		negotiation::accepts(self.get_header("Accept"), types)
+	}
+
+	/// Returns the encoding the client prefers out of the given encodings,
+	/// based on the `Accept-Encoding` header
+	pub fn accepts_encodings(&self, encodings: &[&str]) -> Option<String> {
+		negotiation::accepts_encodings(
+			self.get_header("Accept-Encoding"),
+			encodings,
+		)
+	}
+
+	/// Returns the charset the client prefers out of the given charsets,
+	/// based on the `Accept-Charset` header
+	pub fn accepts_charsets(&self, charsets: &[&str]) -> Option<String> {
+		negotiation::accepts_charsets(
+			self.get_header("Accept-Charset"),
+			charsets,
+		)
 	}
 
-	// TODO content negotiation
-	// See: https://koajs.com/#request content negotiation
+	/// Returns the language the client prefers out of the given languages,
+	/// based on the `Accept-Language` header
+	pub fn accepts_languages(&self, languages: &[&str]) -> Option<String> {
+		negotiation::accepts_languages(
+			self.get_header("Accept-Language"),
+			languages,
+		)
+	}
 
 	pub fn get_version(&self) -> String {
 		format!("{}.{}", self.version.0, self.version.1)
@@ -185,37 +389,56 @@ impl Request {
 		self.version.1
 	}
 
+	/// Returns the value of a header, with repeated values joined by commas
 	pub fn get_header(&self, field: &str) -> Option<String> {
-		self.headers.iter().find_map(|(key, value)| {
-			if key.to_lowercase() == field.to_lowercase() {
-				Some(value.join("\n"))
-			} else {
-				None
-			}
-		})
+		self.headers.get(field)
 	}
-	pub fn get_headers(&self) -> &HashMap<String, Vec<String>> {
+	pub fn get_headers(&self) -> &HeaderMap {
 		&self.headers
 	}
 	pub fn set_header(&mut self, field: &str, value: &str) {
-		self.headers
-			.insert(field.to_string(), vec![value.to_string()]);
+		self.headers.set(field, value);
 	}
 	pub fn append_header(&mut self, key: String, value: String) {
-		if let Some(headers) = self.headers.get_mut(&key) {
-			headers.push(value);
-		} else {
-			self.headers.insert(key, vec![value]);
-		}
+		self.headers.append(&key, &value);
+	}
+	/// Parses a header into its typed value, returning `None` if it isn't set
+	/// or is invalid. Multiple values of the header are parsed as one list.
+	pub fn get_typed_header<THeader: Header>(&self) -> Option<THeader> {
+		THeader::parse(&self.headers.get(THeader::NAME)?)
+	}
+	pub fn set_typed_header<THeader: Header>(&mut self, header: &THeader) {
+		self.set_header(THeader::NAME, &header.format());
 	}
 	pub fn remove_header(&mut self, field: &str) {
 		self.headers.remove(field);
 	}
 
-	pub fn get_query(&self) -> &HashMap<String, String> {
+	/// Returns the ID of the last event received by a reconnecting
+	/// Server-Sent Events client.
+	pub fn get_last_event_id(&self) -> Option<String> {
+		self.get_header("Last-Event-ID")
+	}
+
+	pub fn get_query(&self) -> &QueryMap {
 		&self.query
 	}
 
+	/// Deserializes the query string into a type. The error converts into a
+	/// 400 response that names the invalid parameter.
+	pub fn get_query_as<TQuery>(&self) -> Result<TQuery, QueryError>
+	where
+		TQuery: DeserializeOwned,
+	{
+		self.query.deserialize()
+	}
+
+	/// Returns the pattern of the route that handles the request, such as
+	/// `/users/:id`, or `None` if no route matches it
+	pub fn get_route(&self) -> Option<&str> {
+		self.route.as_deref()
+	}
+
 	pub fn get_params(&self) -> &HashMap<String, String> {
 		&self.params
 	}
@@ -228,6 +451,128 @@ impl Request {
 		self.cookies.iter().find(|cookie| cookie.key == name)
 	}
 
+	/// Returns the protocol the client would most like to upgrade the
+	/// connection to, which is the first one in its `Upgrade` header
+	pub fn get_upgrade_protocol(&self) -> Option<String> {
+		self.get_upgrade_protocols().into_iter().next()
+	}
+
+	/// Returns every protocol the client offered to upgrade the connection
+	/// to, in its order of preference
+	pub fn get_upgrade_protocols(&self) -> Vec<String> {
+		self.get_header("Upgrade")
+			.map(|header| {
+				header
+					.split(',')
+					.map(str::trim)
+					.filter(|protocol| !protocol.is_empty())
+					.map(String::from)
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	/// Returns true if the request is a CONNECT request or asks for the
+	/// connection to be upgraded to another protocol
+	pub fn is_upgrade_request(&self) -> bool {
+		self.method == HttpMethod::Connect ||
+			self.get_upgrade_protocol().is_some()
+	}
+
+	/// Returns true if the client sent `Expect: 100-continue`, and so waits
+	/// for the server to accept the request before uploading the body. The
+	/// `100 Continue` response is only sent once the body is first read, so
+	/// middlewares that run before anything reads the body can reject the
+	/// request (such as when authentication fails) without the body ever
+	/// being sent.
+	pub fn expects_continue(&self) -> bool {
+		self.get_header("Expect")
+			.map(|expect| expect.eq_ignore_ascii_case("100-continue"))
+			.unwrap_or(false)
+	}
+
+	/// Returns a future that resolves to the raw connection once the response
+	/// to this request has been sent, if the response switches protocols (or
+	/// is a successful response to a CONNECT request).
+	pub fn on_upgrade(&mut self) -> OnUpgrade {
+		hyper::upgrade::on(&mut self.hyper_request)
+	}
+
+	pub fn get_pub_sub(&self) -> &PubSub {
+		&self.pub_sub
+	}
+
+	/// Returns a token that's cancelled if the client disconnects before the
+	/// response is sent, or if the request times out. It can be cloned into
+	/// spawned tasks so that they stop work that's no longer needed.
+	pub fn get_cancellation_token(&self) -> &CancellationToken {
+		&self.cancellation_token
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.cancellation_token.is_cancelled()
+	}
+
+	/// Returns the ID of the request, which is set by the
+	/// [`request_id`](crate::default_middlewares::request_id) middleware
+	pub fn get_request_id(&self) -> Option<&str> {
+		self.request_id.as_deref()
+	}
+
+	pub fn set_request_id(&mut self, request_id: &str) {
+		self.request_id = Some(request_id.to_string());
+	}
+
+	/// Returns the value of the given type that a middleware stored on the
+	/// request, such as the authenticated user
+	pub fn get_extension<TValue>(&self) -> Option<&TValue>
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.extensions.get()
+	}
+
+	pub fn get_extension_mut<TValue>(&mut self) -> Option<&mut TValue>
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.extensions.get_mut()
+	}
+
+	/// Stores a value on the request for the middlewares after this one to
+	/// read. Only one value of each type is kept, so the value that was
+	/// already stored, if any, is returned.
+	pub fn insert_extension<TValue>(&mut self, value: TValue) -> Option<TValue>
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.extensions.insert(value)
+	}
+
+	pub fn remove_extension<TValue>(&mut self) -> Option<TValue>
+	where
+		TValue: 'static + Send + Sync,
+	{
+		self.extensions.remove()
+	}
+
+	pub fn get_extensions(&self) -> &Extensions {
+		&self.extensions
+	}
+
+	pub fn get_extensions_mut(&mut self) -> &mut Extensions {
+		&mut self.extensions
+	}
+
+	/// Returns the W3C trace context of the request, which is continued from
+	/// its `traceparent` header if it has one
+	#[cfg(feature = "tracing")]
+	pub fn get_trace_context(&self) -> &TraceContext {
+		&self.trace_context
+	}
+
+	/// Returns the underlying hyper request. Note that its body is empty once
+	/// the request body has been read or taken as a stream.
 	pub fn get_hyper_request(&self) -> &HyperRequest {
 		&self.hyper_request
 	}
@@ -250,6 +595,7 @@ impl Debug for Request {
 			.field("query", &self.query)
 			.field("params", &self.params)
 			.field("cookies", &self.cookies)
+			.field("forwarded", &self.forwarded)
 			.finish()
 	}
 }
@@ -260,3 +606,196 @@ impl Debug for Request {
 		write!(f, "[Request {} {}]", self.method, self.get_path())
 	}
 }
+
+/// An error that occurs while reading the request body. When returned from a
+/// middleware using `?`, it is converted into an [`Error`](crate::Error) with
+/// an appropriate status code, even when it's the source of another error.
+#[derive(Clone, Debug)]
+pub enum BodyError {
+	/// The body has already been taken as a stream and can't be read again
+	Consumed,
+	/// The body could not be read from the connection
+	Read(Arc<HyperError>),
+	/// The body is not valid UTF-8
+	InvalidUtf8(Utf8Error),
+	/// The body is larger than the limit, in bytes, set on the app or route
+	TooLarge(usize),
+}
+
+impl BodyError {
+	pub fn get_status(&self) -> u16 {
+		match self {
+			BodyError::Consumed => 500,
+			BodyError::Read(_) | BodyError::InvalidUtf8(_) => 400,
+			BodyError::TooLarge(_) => 413,
+		}
+	}
+}
+
+impl Display for BodyError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		match self {
+			BodyError::Consumed => {
+				write!(f, "the request body has already been consumed")
+			}
+			BodyError::Read(err) => {
+				write!(f, "unable to read the request body: {}", err)
+			}
+			BodyError::InvalidUtf8(err) => {
+				write!(f, "the request body is not valid UTF-8: {}", err)
+			}
+			BodyError::TooLarge(limit) => write!(
+				f,
+				"the request body is larger than the limit of {} bytes",
+				limit
+			),
+		}
+	}
+}
+
+impl StdError for BodyError {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		match self {
+			BodyError::Read(err) => Some(err.as_ref()),
+			BodyError::InvalidUtf8(err) => Some(err),
+			BodyError::Consumed | BodyError::TooLarge(_) => None,
+		}
+	}
+}
+
+/// Buffers the body, failing as soon as it grows past the limit
+async fn read_limited(
+	mut body: Body,
+	limit: usize,
+) -> Result<Bytes, BodyError> {
+	let mut bytes = Vec::new();
+	while let Some(chunk) = body.data().await {
+		let chunk = chunk.map_err(get_read_error)?;
+		if bytes.len() + chunk.len() > limit {
+			return Err(BodyError::TooLarge(limit));
+		}
+		bytes.extend_from_slice(&chunk);
+	}
+	Ok(Bytes::from(bytes))
+}
+
+/// Returns the error for a body that failed to be read. Bodies that were
+/// already limited fail with the limit they exceeded rather than as a read
+/// error.
+fn get_read_error(err: HyperError) -> BodyError {
+	match err.source().and_then(|err| err.downcast_ref::<BodyError>()) {
+		Some(err) => err.clone(),
+		None => BodyError::Read(Arc::new(err)),
+	}
+}
+
+/// Wraps the body in a stream that errors once more than `limit` bytes have
+/// been read from it
+fn limit_body(body: Body, limit: usize) -> Body {
+	Body::wrap_stream(stream::try_unfold(
+		(body, 0),
+		move |(mut body, read)| async move {
+			let Some(chunk) = body.data().await.transpose()? else {
+				return Ok(None);
+			};
+			let read = read + chunk.len();
+			if read > limit {
+				return Err(Box::new(BodyError::TooLarge(limit)) as BoxError);
+			}
+			Ok(Some((chunk, (body, read))))
+		},
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Error as IoError;
+
+	use super::*;
+	use crate::Error;
+
+	fn request_with_body(body: Body) -> Request {
+		Request::from_hyper(
+			"127.0.0.1:8080".parse().unwrap(),
+			HyperRequest::new(body),
+		)
+	}
+
+	fn failing_body() -> Body {
+		Body::wrap_stream(stream::iter(vec![
+			Ok(Bytes::from("partial")),
+			Err(IoError::other("connection reset")),
+		]))
+	}
+
+	#[tokio::test]
+	async fn read_errors_are_remembered() {
+		let mut request = request_with_body(failing_body());
+
+		let first = request.get_body().await.unwrap_err();
+		assert!(matches!(first, BodyError::Read(_)));
+		assert_eq!(first.get_status(), 400);
+
+		let second = request.get_body_bytes().await.unwrap_err();
+		assert!(matches!(second, BodyError::Read(_)));
+		assert!(matches!(
+			request.take_body_stream(),
+			Err(BodyError::Read(_))
+		));
+	}
+
+	#[tokio::test]
+	async fn body_errors_convert_with_their_status() {
+		let mut request = request_with_body(failing_body());
+		let err = request.get_body().await.unwrap_err();
+		assert!(err.source().is_some());
+
+		let error: Error<()> = err.into();
+		assert_eq!(error.get_status(), Some(400));
+		let error: Error<()> = BodyError::TooLarge(10).into();
+		assert_eq!(error.get_status(), Some(413));
+	}
+
+	fn chunked_body() -> Body {
+		Body::wrap_stream(stream::iter(vec![
+			Ok::<_, IoError>(Bytes::from("chunk 1")),
+			Ok(Bytes::from("chunk 2")),
+		]))
+	}
+
+	#[tokio::test]
+	async fn streamed_bodies_over_the_limit_are_too_large() {
+		let mut request = request_with_body(chunked_body());
+		request.body_limit = Some(10);
+		let stream = request.take_body_stream().unwrap();
+
+		let err = body::to_bytes(stream).await.unwrap_err();
+		let error: Error<()> = err.into();
+		assert_eq!(error.get_status(), Some(413));
+	}
+
+	#[tokio::test]
+	async fn chunked_bodies_over_the_limit_are_too_large() {
+		let mut request = request_with_body(chunked_body());
+		request.body_limit = Some(10);
+		assert!(matches!(
+			request.get_body_bytes().await,
+			Err(BodyError::TooLarge(10))
+		));
+
+		// A limited stream read again by another limited request
+		let mut request = request_with_body(chunked_body());
+		request.body_limit = Some(10);
+		let mut request =
+			request_with_body(request.take_body_stream().unwrap());
+		request.body_limit = Some(100);
+		assert!(matches!(
+			request.get_body_bytes().await,
+			Err(BodyError::TooLarge(10))
+		));
+
+		let mut request = request_with_body(chunked_body());
+		request.body_limit = Some(14);
+		assert_eq!(request.get_body().await.unwrap(), "chunk 1chunk 2");
+	}
+}
@@ -1,25 +1,52 @@
 use std::{
-	collections::HashMap,
+	error::Error as StdError,
 	fmt::{Debug, Formatter, Result as FmtResult},
+	io::Result as IoResult,
+	sync::{Arc, Mutex},
+	time::Duration,
 };
 
 use chrono::Local;
+use futures::Stream;
+use hyper::{
+	body::Bytes,
+	http::Error as HttpError,
+	Body,
+	Response as HyperResponse,
+};
+use serde_json::{Map, Value};
+use tokio::{fs::File, io::AsyncRead};
+use tokio_util::io::ReaderStream;
 
-use crate::Cookie;
+use crate::{
+	headers::{ContentDisposition, Header, HeaderMap},
+	Cookie,
+	ServerTiming,
+};
 
 #[derive(Clone)]
 pub struct Response {
 	pub(crate) body: Vec<u8>,
+	pub(crate) body_stream: Option<BodyStream>,
 	pub(crate) status: u16,
-	pub(crate) headers: HashMap<String, Vec<String>>,
+	pub(crate) headers: HeaderMap,
+	pub(crate) timings: Vec<ServerTiming>,
+	pub(crate) locals: Map<String, Value>,
+	#[cfg(feature = "websocket")]
+	pub(crate) websocket_handler: Option<usize>,
 }
 
 impl Response {
 	pub fn new() -> Self {
 		Response {
 			body: vec![],
+			body_stream: None,
 			status: 200,
-			headers: HashMap::new(),
+			headers: HeaderMap::new(),
+			timings: vec![],
+			locals: Map::new(),
+			#[cfg(feature = "websocket")]
+			websocket_handler: None,
 		}
 	}
 
@@ -97,29 +124,28 @@ impl Response {
 		self.set_header("content-length", &format!("{}", length));
 	}
 
-	pub fn get_headers(&self) -> &HashMap<String, Vec<String>> {
+	pub fn get_headers(&self) -> &HeaderMap {
 		&self.headers
 	}
+	/// Returns the value of a header, with repeated values joined by commas.
+	/// `Set-Cookie` headers can't be joined, so read them from
+	/// [`get_headers`](Self::get_headers) instead.
 	pub fn get_header(&self, field: &str) -> Option<String> {
-		self.headers.iter().find_map(|(key, value)| {
-			if key.to_lowercase() == field.to_lowercase() {
-				Some(value.join("\n"))
-			} else {
-				None
-			}
-		})
+		self.headers.get(field)
 	}
 	pub fn set_header(&mut self, key: &str, value: &str) {
-		self.headers
-			.insert(key.to_string(), vec![value.to_string()]);
+		self.headers.set(key, value);
 	}
 	pub fn append_header(&mut self, key: &str, value: &str) {
-		if let Some(headers) = self.headers.get_mut(key) {
-			headers.push(value.to_string());
-		} else {
-			self.headers
-				.insert(key.to_string(), vec![value.to_string()]);
-		}
+		self.headers.append(key, value);
+	}
+	/// Parses a header into its typed value, returning `None` if it isn't set
+	/// or is invalid. Multiple values of the header are parsed as one list.
+	pub fn get_typed_header<THeader: Header>(&self) -> Option<THeader> {
+		THeader::parse(&self.headers.get(THeader::NAME)?)
+	}
+	pub fn set_typed_header<THeader: Header>(&mut self, header: &THeader) {
+		self.set_header(THeader::NAME, &header.format());
 	}
 	pub fn remove_header(&mut self, field: &str) {
 		self.headers.remove(field);
@@ -143,17 +169,7 @@ impl Response {
 	}
 
 	pub fn attachment(&mut self, file_name: Option<&str>) {
-		self.set_header(
-			"Content-Disposition",
-			&format!(
-				"attachment{}",
-				if let Some(filename) = file_name {
-					format!("; filename=\"{}\"", filename)
-				} else {
-					String::new()
-				}
-			),
-		);
+		self.set_typed_header(&ContentDisposition::attachment(file_name));
 	}
 
 	pub fn get_last_modified(&self) -> Option<String> {
@@ -167,6 +183,8 @@ impl Response {
 		self.set_header("ETag", etag);
 	}
 
+	/// Returns the buffered body. This is empty if the body is being
+	/// streamed.
 	pub fn get_body(&self) -> &Vec<u8> {
 		&self.body
 	}
@@ -175,13 +193,104 @@ impl Response {
 	}
 	pub fn set_body_bytes(&mut self, data: &[u8]) {
 		self.body = data.to_vec();
+		self.body_stream = None;
 		self.set_content_length(data.len());
 		self.set_header("date", &Local::now().to_rfc2822());
 	}
 
+	/// Sets a stream of chunks as the body. Since the length isn't known
+	/// upfront, the body is sent using chunked transfer encoding.
+	pub fn set_body_stream<TStream, TChunk, TError>(&mut self, stream: TStream)
+	where
+		TStream: Stream<Item = Result<TChunk, TError>> + Send + 'static,
+		TChunk: Into<Bytes> + 'static,
+		TError: Into<Box<dyn StdError + Send + Sync>> + 'static,
+	{
+		self.body = vec![];
+		self.body_stream = Some(BodyStream::new(Body::wrap_stream(stream)));
+		self.remove_header("content-length");
+		self.set_header("date", &Local::now().to_rfc2822());
+	}
+	pub fn set_body_reader<TReader>(&mut self, reader: TReader)
+	where
+		TReader: AsyncRead + Send + 'static,
+	{
+		self.set_body_stream(ReaderStream::new(reader));
+	}
+	/// Streams the contents of the file as the body, setting the
+	/// content-length from the file's metadata.
+	pub async fn set_body_file(&mut self, file: File) -> IoResult<()> {
+		let length = file.metadata().await?.len();
+		self.set_body_reader(file);
+		self.set_content_length(length as usize);
+		Ok(())
+	}
+	pub fn is_streaming(&self) -> bool {
+		self.body_stream.is_some()
+	}
+	/// Takes the body stream out of the response, if the body is being
+	/// streamed. Clones of a response share its stream, so only one of them
+	/// can take it.
+	pub fn take_body_stream(&mut self) -> Option<Body> {
+		self.body_stream.take()?.take()
+	}
+
+	/// Adds a timing to send in the `Server-Timing` header, which is set by
+	/// the [`server_timing`](crate::default_middlewares::server_timing)
+	/// middleware
+	pub fn add_timing(
+		&mut self,
+		name: &str,
+		duration: Duration,
+		description: Option<&str>,
+	) {
+		self.timings.push(ServerTiming {
+			name: name.to_string(),
+			duration,
+			description: description.map(str::to_string),
+		});
+	}
+	pub fn get_timings(&self) -> &[ServerTiming] {
+		&self.timings
+	}
+
+	/// Returns the values that are made available to templates rendered for
+	/// this response, alongside the data passed to `render`
+	pub fn get_locals(&self) -> &Map<String, Value> {
+		&self.locals
+	}
+
+	pub fn get_locals_mut(&mut self) -> &mut Map<String, Value> {
+		&mut self.locals
+	}
+
+	pub fn set_local(&mut self, key: &str, value: Value) {
+		self.locals.insert(key.to_string(), value);
+	}
+
 	pub fn set_cookie(&mut self, cookie: Cookie) {
 		self.append_header("Set-Cookie", &cookie.to_header_string());
 	}
+
+	/// Converts the response into a hyper response. Fails if the status or a
+	/// header set by a middleware isn't valid.
+	pub(crate) fn into_hyper_response(
+		mut self,
+	) -> Result<HyperResponse<Body>, HttpError> {
+		let body = self
+			.take_body_stream()
+			.unwrap_or_else(|| Body::from(self.body));
+		let mut hyper_response = HyperResponse::builder();
+
+		// Set the appropriate headers
+		for (key, values) in self.headers.iter() {
+			for value in values {
+				hyper_response = hyper_response.header(key, value);
+			}
+		}
+
+		hyper_response.status(self.status).body(body)
+	}
 }
 
 impl Debug for Response {
@@ -189,6 +298,7 @@ impl Debug for Response {
 		if cfg!(debug_assertions) {
 			f.debug_struct("Request")
 				.field("body", &self.body)
+				.field("is_streaming", &self.is_streaming())
 				.field("status", &self.status)
 				.field("headers", &self.headers)
 				.finish()
@@ -200,10 +310,22 @@ impl Debug for Response {
 
 impl Default for Response {
 	fn default() -> Self {
-		Response {
-			body: vec![],
-			status: 200,
-			headers: HashMap::new(),
-		}
+		Response::new()
+	}
+}
+
+/// A body stream that can be cloned along with the response. A stream can
+/// only be read once, so clones share it, and the first one to take it gets
+/// it.
+#[derive(Clone)]
+pub(crate) struct BodyStream(Arc<Mutex<Option<Body>>>);
+
+impl BodyStream {
+	pub(crate) fn new(body: Body) -> Self {
+		BodyStream(Arc::new(Mutex::new(Some(body))))
+	}
+
+	fn take(&self) -> Option<Body> {
+		self.0.lock().ok()?.take()
 	}
 }
@@ -0,0 +1,38 @@
+use std::{
+	fmt::{Display, Formatter, Result as FmtResult},
+	time::Duration,
+};
+
+/// A named duration sent in the `Server-Timing` header
+#[derive(Clone, Debug)]
+pub struct ServerTiming {
+	pub name: String,
+	pub duration: Duration,
+	pub description: Option<String>,
+}
+
+impl Display for ServerTiming {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		// Names have to be tokens, so replace anything that isn't allowed
+		let name = self
+			.name
+			.chars()
+			.map(|c| {
+				if c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c) {
+					c
+				} else {
+					'_'
+				}
+			})
+			.collect::<String>();
+		write!(f, "{}", name)?;
+		if let Some(description) = &self.description {
+			write!(
+				f,
+				";desc=\"{}\"",
+				description.replace('\\', "\\\\").replace('"', "\\\"")
+			)?;
+		}
+		write!(f, ";dur={:.3}", self.duration.as_secs_f64() * 1000.0)
+	}
+}
@@ -0,0 +1,270 @@
+use std::{
+	error::Error as StdError,
+	fmt::Debug,
+	future::{self, Future},
+	pin::Pin,
+	sync::Arc,
+};
+
+use futures::stream;
+use hyper::{
+	body::{self, Buf, Bytes, HttpBody},
+	service::Service,
+	Body,
+	Request as HyperRequest,
+	Response as HyperResponse,
+	Uri,
+};
+use regex::Regex;
+
+use crate::{
+	error::AsError,
+	middleware_handler::get_path_match,
+	response::BodyStream,
+	Context,
+	Error,
+};
+
+pub(crate) type BoxError = Box<dyn StdError + Send + Sync>;
+
+type ServiceHandler = Arc<
+	dyn Fn(
+			HyperRequest<Body>,
+		) -> Pin<
+			Box<
+				dyn Future<Output = Result<HyperResponse<Body>, BoxError>>
+					+ Send,
+			>,
+		> + Send
+		+ Sync,
+>;
+
+/// The largest body of a known size that's buffered when a service responds,
+/// instead of being streamed
+const MAX_BUFFERED_BODY_SIZE: u64 = 64 * 1024;
+
+/// The responses of services that can be sent by eve, which are hyper
+/// responses with any kind of body
+#[async_trait::async_trait]
+pub trait ServiceResponse: Send + 'static {
+	async fn into_hyper_response(self) -> HyperResponse<Body>;
+}
+
+#[async_trait::async_trait]
+impl<TBody> ServiceResponse for HyperResponse<TBody>
+where
+	TBody: HttpBody + Send + 'static,
+	TBody::Data: Send,
+	TBody::Error: Into<BoxError>,
+{
+	async fn into_hyper_response(self) -> HyperResponse<Body> {
+		let (parts, body) = self.into_parts();
+		HyperResponse::from_parts(parts, buffer_body(body).await)
+	}
+}
+
+/// A service mounted on a path using `App::mount_service`
+#[derive(Clone)]
+pub(crate) struct MountedService {
+	pub(crate) mounted_url: String,
+	pub(crate) path_match: Regex,
+	pub(crate) handler: ServiceHandler,
+}
+
+impl MountedService {
+	pub(crate) fn new<TService>(path: &str, service: TService) -> Self
+	where
+		TService: Service<HyperRequest<Body>> + Clone + Send + Sync + 'static,
+		TService::Response: ServiceResponse,
+		TService::Error: Into<BoxError>,
+		TService::Future: Send,
+	{
+		Self::new_with_handler(
+			path,
+			Arc::new(move |request| {
+				let mut service = service.clone();
+				Box::pin(
+					async move { call_service(&mut service, request).await },
+				)
+			}),
+		)
+	}
+
+	pub(crate) fn new_with_handler(
+		path: &str,
+		handler: ServiceHandler,
+	) -> Self {
+		let (mounted_url, path_match) = get_path_match(path, false);
+		// Only match from the start of the path, so that the rest of the path
+		// can be handed to the service
+		let path_match =
+			Regex::new(&format!("^{}", path_match.as_str())).unwrap();
+		MountedService {
+			mounted_url,
+			path_match,
+			handler,
+		}
+	}
+
+	pub(crate) fn is_match(&self, path: &str) -> bool {
+		self.get_relative_path(path).is_some()
+	}
+
+	/// Returns the part of the path after the mounted path, which always
+	/// begins with a `/`
+	fn get_relative_path(&self, path: &str) -> Option<String> {
+		let matched = self.path_match.find(path)?;
+		let rest = &path[matched.end()..];
+		if matched.as_str().ends_with('/') {
+			Some(format!("/{}", rest))
+		} else if rest.is_empty() {
+			Some("/".to_string())
+		} else if rest.starts_with('/') {
+			Some(rest.to_string())
+		} else {
+			// The mounted path only matched part of a path segment
+			None
+		}
+	}
+
+	/// Hands the request to the service, with the mounted path removed from
+	/// the URI, and sets the response of the context to its response
+	pub(crate) async fn serve<TContext, TErrorData>(
+		&self,
+		mut context: TContext,
+	) -> Result<TContext, Error<TErrorData>>
+	where
+		TContext: Context + Debug + Send + Sync,
+		TErrorData: Default + Send + Sync,
+	{
+		let relative_path = self
+			.get_relative_path(&context.get_path())
+			.unwrap_or_else(|| "/".to_string());
+		let mut request = context.get_request_mut().take_hyper_request()?;
+		let uri = match request.uri().query() {
+			Some(query) => format!("{}?{}", relative_path, query),
+			None => relative_path,
+		};
+		*request.uri_mut() = uri.parse::<Uri>().status(400)?;
+
+		let response = (self.handler)(request)
+			.await
+			.map_err(|err| Error::<TErrorData>::new(err).status(500))?;
+		set_response(&mut context, response, false).await;
+		Ok(context)
+	}
+}
+
+/// Waits for the service to be ready and calls it with the request
+pub(crate) async fn call_service<TService, TBody>(
+	service: &mut TService,
+	request: HyperRequest<TBody>,
+) -> Result<HyperResponse<Body>, BoxError>
+where
+	TService: Service<HyperRequest<TBody>>,
+	TService::Response: ServiceResponse,
+	TService::Error: Into<BoxError>,
+{
+	future::poll_fn(|cx| service.poll_ready(cx))
+		.await
+		.map_err(Into::into)?;
+	let response = service.call(request).await.map_err(Into::into)?;
+	Ok(response.into_hyper_response().await)
+}
+
+/// Sets the response of the context to the response of a service. Headers
+/// set by the service replace the ones already set, and every other header
+/// is removed if `replace_headers` is true.
+///
+/// Small bodies of a known size are set as the body of the response, so that
+/// middlewares like the compression middleware can modify them. Every other
+/// body is streamed, and is sent as it is.
+pub(crate) async fn set_response<TContext>(
+	context: &mut TContext,
+	response: HyperResponse<Body>,
+	replace_headers: bool,
+) where
+	TContext: Context + Debug + Send + Sync,
+{
+	let (parts, body) = response.into_parts();
+	let response = context.get_response_mut();
+
+	response.status = parts.status.as_u16();
+	if replace_headers {
+		response.headers.clear();
+	} else {
+		response
+			.headers
+			.retain(|key, _| !parts.headers.contains_key(key));
+	}
+	for (key, value) in &parts.headers {
+		response.append_header(
+			key.as_str(),
+			&String::from_utf8_lossy(value.as_bytes()),
+		);
+	}
+	if !is_bufferable(&body) {
+		response.body = vec![];
+		response.body_stream = Some(BodyStream::new(body));
+		return;
+	}
+	match body::to_bytes(body).await {
+		Ok(bytes) => {
+			response.body = bytes.to_vec();
+			response.body_stream = None;
+		}
+		Err(err) => {
+			response.body = vec![];
+			response.body_stream =
+				Some(BodyStream::new(get_error_body(err.into())));
+		}
+	}
+}
+
+fn is_bufferable<TBody>(body: &TBody) -> bool
+where
+	TBody: HttpBody,
+{
+	matches!(
+		body.size_hint().exact(),
+		Some(size) if size <= MAX_BUFFERED_BODY_SIZE
+	)
+}
+
+/// Reads small bodies of a known size into memory, so that the size is still
+/// known once the body is converted, and streams every other body
+async fn buffer_body<TBody>(body: TBody) -> Body
+where
+	TBody: HttpBody + Send + 'static,
+	TBody::Data: Send,
+	TBody::Error: Into<BoxError>,
+{
+	if !is_bufferable(&body) {
+		return into_body(body);
+	}
+	match body::to_bytes(body).await {
+		Ok(bytes) => Body::from(bytes),
+		Err(err) => get_error_body(err.into()),
+	}
+}
+
+/// Returns a body that fails with the error when it's read, for bodies that
+/// failed while being buffered
+fn get_error_body(err: BoxError) -> Body {
+	Body::wrap_stream(stream::once(future::ready(Err::<Bytes, _>(err))))
+}
+
+pub(crate) fn into_body<TBody>(body: TBody) -> Body
+where
+	TBody: HttpBody + Send + 'static,
+	TBody::Data: Send,
+	TBody::Error: Into<BoxError>,
+{
+	Body::wrap_stream(stream::unfold(Box::pin(body), |mut body| async move {
+		let chunk = body.data().await?;
+		Some((
+			chunk.map(|mut data| data.copy_to_bytes(data.remaining())),
+			body,
+		))
+	}))
+}
@@ -0,0 +1,231 @@
+use std::{
+	convert::Infallible,
+	fmt::Write,
+	io::{Error as IoError, ErrorKind},
+	time::Duration,
+};
+
+use futures::{stream, Stream};
+use tokio::{
+	sync::mpsc::{self, Receiver, Sender},
+	time::{self, Instant, Interval},
+};
+
+use crate::pub_sub::Subscription;
+
+const KEEP_ALIVE_COMMENT: &str = ": keep-alive\n\n";
+
+/// A single event sent over a Server-Sent Events stream.
+#[derive(Clone, Debug, Default)]
+pub struct SseEvent {
+	id: Option<String>,
+	event: Option<String>,
+	data: Option<String>,
+	retry: Option<Duration>,
+	comment: Option<String>,
+}
+
+impl SseEvent {
+	pub fn new(data: &str) -> Self {
+		SseEvent {
+			data: Some(data.to_string()),
+			..Default::default()
+		}
+	}
+
+	/// Creates an event that only contains a comment. Browsers ignore
+	/// comments, but they keep the connection active.
+	pub fn comment(comment: &str) -> Self {
+		SseEvent {
+			comment: Some(comment.to_string()),
+			..Default::default()
+		}
+	}
+
+	pub fn id(mut self, id: &str) -> Self {
+		self.id = Some(id.to_string());
+		self
+	}
+
+	pub fn event(mut self, event: &str) -> Self {
+		self.event = Some(event.to_string());
+		self
+	}
+
+	pub fn data(mut self, data: &str) -> Self {
+		self.data = Some(data.to_string());
+		self
+	}
+
+	pub fn retry(mut self, retry: Duration) -> Self {
+		self.retry = Some(retry);
+		self
+	}
+
+	/// Formats the event according to the `text/event-stream` format.
+	pub fn to_event_string(&self) -> String {
+		let mut output = String::new();
+
+		if let Some(comment) = &self.comment {
+			for line in split_lines(comment) {
+				let _ = writeln!(output, ": {}", line);
+			}
+		}
+		// Newlines aren't allowed in the id and event fields, as they would
+		// terminate the field
+		if let Some(id) = &self.id {
+			let _ = writeln!(output, "id: {}", id.replace(['\r', '\n'], ""));
+		}
+		if let Some(event) = &self.event {
+			let _ =
+				writeln!(output, "event: {}", event.replace(['\r', '\n'], ""));
+		}
+		if let Some(retry) = &self.retry {
+			let _ = writeln!(output, "retry: {}", retry.as_millis());
+		}
+		if let Some(data) = &self.data {
+			// Each line of the data has to be sent as a separate data field
+			for line in split_lines(data) {
+				let _ = writeln!(output, "data: {}", line);
+			}
+		}
+
+		output.push('\n');
+		output
+	}
+}
+
+/// Splits the value on every line ending the event stream format accepts,
+/// which are `\r\n`, `\r` and `\n`. Unlike [`str::lines`], empty trailing
+/// lines are kept.
+fn split_lines(value: &str) -> impl Iterator<Item = &str> {
+	value
+		.split("\r\n")
+		.flat_map(|line| line.split(['\r', '\n']))
+}
+
+/// The sending half of a Server-Sent Events stream, created using
+/// [`Context::sse`](crate::Context::sse). The response is only sent once the
+/// middleware chain completes, so the sender should be moved into a spawned
+/// task that produces the events.
+#[derive(Clone, Debug)]
+pub struct SseSender {
+	sender: Sender<String>,
+}
+
+impl SseSender {
+	/// Sends an event to the client. Returns an error with the kind
+	/// [`ErrorKind::BrokenPipe`] once the client has disconnected.
+	pub async fn send(&self, event: SseEvent) -> Result<(), IoError> {
+		self.sender
+			.send(event.to_event_string())
+			.await
+			.map_err(|_| IoError::from(ErrorKind::BrokenPipe))
+	}
+
+	/// Returns true if the client has disconnected
+	pub fn is_closed(&self) -> bool {
+		self.sender.is_closed()
+	}
+
+	/// Waits for the client to disconnect
+	pub async fn closed(&self) {
+		self.sender.closed().await
+	}
+
+	/// Sends every message of the subscription to the client as the data of
+	/// an event, until either the client disconnects or the subscription
+	/// ends. The subscription is dropped when the client disconnects.
+	pub async fn forward(&self, mut subscription: Subscription) {
+		loop {
+			tokio::select! {
+				message = subscription.recv() => {
+					let Some(message) = message else {
+						return;
+					};
+					if self.send(SseEvent::new(&message)).await.is_err() {
+						return;
+					}
+				}
+				_ = self.closed() => return,
+			}
+		}
+	}
+}
+
+pub(crate) fn channel(
+	keep_alive: Option<Duration>,
+) -> (SseSender, impl Stream<Item = Result<String, Infallible>>) {
+	let (sender, receiver) = mpsc::channel(16);
+	let interval = keep_alive.map(|keep_alive| {
+		time::interval_at(Instant::now() + keep_alive, keep_alive)
+	});
+
+	(SseSender { sender }, event_stream(receiver, interval))
+}
+
+fn event_stream(
+	receiver: Receiver<String>,
+	interval: Option<Interval>,
+) -> impl Stream<Item = Result<String, Infallible>> {
+	stream::unfold(
+		(receiver, interval),
+		|(mut receiver, mut interval)| async move {
+			// The stream ends once every sender has been dropped
+			let chunk = if let Some(interval) = &mut interval {
+				tokio::select! {
+					event = receiver.recv() => event?,
+					_ = interval.tick() => KEEP_ALIVE_COMMENT.to_string(),
+				}
+			} else {
+				receiver.recv().await?
+			};
+			Some((Ok(chunk), (receiver, interval)))
+		},
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn each_line_of_data_is_a_field() {
+		assert_eq!(
+			SseEvent::new("first\nsecond\r\nthird").to_event_string(),
+			"data: first\ndata: second\ndata: third\n\n"
+		);
+		assert_eq!(SseEvent::new("").to_event_string(), "data: \n\n");
+	}
+
+	#[test]
+	fn carriage_returns_end_lines() {
+		assert_eq!(
+			SseEvent::new("x\rid: evil").to_event_string(),
+			"data: x\ndata: id: evil\n\n"
+		);
+		assert_eq!(
+			SseEvent::comment("x\rretry: 0").to_event_string(),
+			": x\n: retry: 0\n\n"
+		);
+	}
+
+	#[test]
+	fn trailing_newlines_are_kept() {
+		assert_eq!(
+			SseEvent::new("line\n").to_event_string(),
+			"data: line\ndata: \n\n"
+		);
+	}
+
+	#[test]
+	fn newlines_are_removed_from_ids_and_events() {
+		assert_eq!(
+			SseEvent::new("data")
+				.id("1\r\ndata: evil")
+				.event("update\revent: evil")
+				.to_event_string(),
+			"id: 1data: evil\nevent: updateevent: evil\ndata: data\n\n"
+		);
+	}
+}
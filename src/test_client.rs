@@ -0,0 +1,334 @@
+use std::{
+	fmt::Debug,
+	net::{Ipv4Addr, SocketAddr},
+	ops::Deref,
+	sync::Arc,
+};
+
+use hyper::{body, Body, Method, Request as HyperRequest};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{handle_request, App, Context, HttpMethod, Middleware, Response};
+
+/// Sends requests through an [`App`] without binding to a port. Requests go
+/// through the same pipeline as [`listen`](crate::listen), including the
+/// error handler, panic handling and the default headers.
+pub struct TestClient<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	app: Arc<App<TContext, TMiddleware, TState, TErrorData>>,
+}
+
+impl<TContext, TMiddleware, TState, TErrorData>
+	TestClient<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TMiddleware:
+		'static + Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TState: 'static + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	pub fn new(app: App<TContext, TMiddleware, TState, TErrorData>) -> Self {
+		TestClient { app: Arc::new(app) }
+	}
+
+	pub fn get_app(&self) -> &App<TContext, TMiddleware, TState, TErrorData> {
+		&self.app
+	}
+
+	/// Starts building a request with the given method. The path may include
+	/// a query string.
+	pub fn request(
+		&self,
+		method: HttpMethod,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		TestRequest {
+			client: self,
+			method: Method::from(method),
+			path: path.to_string(),
+			headers: vec![],
+			cookies: vec![],
+			body: vec![],
+			remote_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+		}
+	}
+
+	pub fn get(
+		&self,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		self.request(HttpMethod::Get, path)
+	}
+
+	pub fn post(
+		&self,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		self.request(HttpMethod::Post, path)
+	}
+
+	pub fn put(
+		&self,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		self.request(HttpMethod::Put, path)
+	}
+
+	pub fn delete(
+		&self,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		self.request(HttpMethod::Delete, path)
+	}
+
+	pub fn head(
+		&self,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		self.request(HttpMethod::Head, path)
+	}
+
+	pub fn options(
+		&self,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		self.request(HttpMethod::Options, path)
+	}
+
+	pub fn patch(
+		&self,
+		path: &str,
+	) -> TestRequest<'_, TContext, TMiddleware, TState, TErrorData> {
+		self.request(HttpMethod::Patch, path)
+	}
+}
+
+impl<TContext, TMiddleware, TState, TErrorData> Clone
+	for TestClient<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	fn clone(&self) -> Self {
+		TestClient {
+			app: self.app.clone(),
+		}
+	}
+}
+
+/// A request being built by a [`TestClient`]. Nothing is sent until
+/// [`send`](TestRequest::send) is called.
+pub struct TestRequest<'a, TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	client: &'a TestClient<TContext, TMiddleware, TState, TErrorData>,
+	method: Method,
+	path: String,
+	headers: Vec<(String, String)>,
+	cookies: Vec<(String, String)>,
+	body: Vec<u8>,
+	remote_addr: SocketAddr,
+}
+
+impl<'a, TContext, TMiddleware, TState, TErrorData>
+	TestRequest<'a, TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TMiddleware:
+		'static + Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TState: 'static + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	pub fn header(mut self, key: &str, value: &str) -> Self {
+		self.headers.push((key.to_string(), value.to_string()));
+		self
+	}
+
+	pub fn cookie(mut self, name: &str, value: &str) -> Self {
+		self.cookies.push((name.to_string(), value.to_string()));
+		self
+	}
+
+	pub fn body(self, body: &str) -> Self {
+		self.body_bytes(body.as_bytes())
+	}
+
+	pub fn body_bytes(mut self, body: &[u8]) -> Self {
+		self.body = body.to_vec();
+		self
+	}
+
+	/// Serializes the value as the JSON body of the request and sets the
+	/// content type accordingly
+	pub fn json<TBody>(self, body: &TBody) -> Self
+	where
+		TBody: Serialize,
+	{
+		let body = serde_json::to_vec(body)
+			.expect("unable to serialize the request body as JSON");
+		self.header("Content-Type", "application/json")
+			.body_bytes(&body)
+	}
+
+	/// Sets the address the request appears to come from. Defaults to
+	/// `127.0.0.1:0`.
+	pub fn remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+		self.remote_addr = remote_addr;
+		self
+	}
+
+	/// Runs the request through the app and buffers the entire response.
+	/// Panics if the response body can't be read, such as when it's an
+	/// endless stream that errors.
+	pub async fn send(self) -> TestResponse {
+		let mut request = HyperRequest::builder()
+			.method(self.method)
+			.uri(self.path.as_str());
+
+		let has_header = |name: &str| {
+			self.headers
+				.iter()
+				.any(|(key, _)| key.eq_ignore_ascii_case(name))
+		};
+		// Set the headers that a real client would always send
+		if !has_header("Host") {
+			request = request.header("Host", "localhost");
+		}
+		if !self.body.is_empty() && !has_header("Content-Length") {
+			request = request.header("Content-Length", self.body.len());
+		}
+		if !self.cookies.is_empty() {
+			let cookies = self
+				.cookies
+				.iter()
+				.map(|(name, value)| format!("{}={}", name, value))
+				.collect::<Vec<_>>()
+				.join("; ");
+			request = request.header("Cookie", cookies);
+		}
+		for (key, value) in &self.headers {
+			request = request.header(key, value);
+		}
+
+		let request = request
+			.body(Body::from(self.body))
+			.expect("unable to build the test request");
+		let hyper_response =
+			handle_request(self.client.app.clone(), self.remote_addr, request)
+				.await
+				.expect("unable to handle the test request");
+
+		let mut response = Response::new();
+		response.set_status(hyper_response.status().as_u16());
+		for (key, value) in hyper_response.headers() {
+			response.append_header(
+				key.as_str(),
+				&String::from_utf8_lossy(value.as_bytes()),
+			);
+		}
+		let body = body::to_bytes(hyper_response.into_body())
+			.await
+			.expect("unable to read the response body");
+		response.body = body.to_vec();
+
+		TestResponse { response }
+	}
+}
+
+/// A response received by a [`TestClient`], with its body fully read. It
+/// derefs to a [`Response`], and has helpers to make assertions on it.
+#[derive(Debug)]
+pub struct TestResponse {
+	response: Response,
+}
+
+impl TestResponse {
+	pub fn get_response(&self) -> &Response {
+		&self.response
+	}
+
+	pub fn into_response(self) -> Response {
+		self.response
+	}
+
+	/// Returns the body as a string, replacing invalid UTF-8
+	pub fn get_text(&self) -> String {
+		String::from_utf8_lossy(&self.response.body).to_string()
+	}
+
+	pub fn get_json<TBody>(&self) -> Result<TBody, serde_json::Error>
+	where
+		TBody: DeserializeOwned,
+	{
+		serde_json::from_slice(&self.response.body)
+	}
+
+	#[track_caller]
+	pub fn assert_status(&self, status: u16) -> &Self {
+		assert_eq!(
+			self.response.status,
+			status,
+			"unexpected status, with body `{}`",
+			self.get_text()
+		);
+		self
+	}
+
+	#[track_caller]
+	pub fn assert_header(&self, key: &str, value: &str) -> &Self {
+		match self.response.get_header(key) {
+			Some(header) => assert_eq!(
+				header, value,
+				"unexpected value for header `{}`",
+				key
+			),
+			None => panic!("expected header `{}` to be set", key),
+		}
+		self
+	}
+
+	#[track_caller]
+	pub fn assert_no_header(&self, key: &str) -> &Self {
+		if let Some(value) = self.response.get_header(key) {
+			panic!("expected header `{}` to not be set, got `{}`", key, value);
+		}
+		self
+	}
+
+	#[track_caller]
+	pub fn assert_body(&self, body: &str) -> &Self {
+		assert_eq!(self.get_text(), body, "unexpected body");
+		self
+	}
+
+	#[track_caller]
+	pub fn assert_body_contains(&self, text: &str) -> &Self {
+		let body = self.get_text();
+		assert!(
+			body.contains(text),
+			"expected body to contain `{}`, got `{}`",
+			text,
+			body
+		);
+		self
+	}
+}
+
+impl Deref for TestResponse {
+	type Target = Response;
+
+	fn deref(&self) -> &Response {
+		&self.response
+	}
+}
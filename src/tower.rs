@@ -0,0 +1,431 @@
+use std::{
+	convert::Infallible,
+	fmt::Debug,
+	future::{self, Future, Ready},
+	mem,
+	net::{Ipv4Addr, SocketAddr},
+	pin::Pin,
+	sync::{Arc, Mutex},
+	task::{Context as PollContext, Poll},
+};
+
+use hyper::{
+	body::{self, HttpBody},
+	server::conn::AddrStream,
+	Body,
+	Error as HyperError,
+	Request as HyperRequest,
+	Response as HyperResponse,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+	handle_request,
+	service::{
+		call_service,
+		into_body,
+		set_response,
+		BoxError,
+		ServiceResponse,
+	},
+	App,
+	Context,
+	Error,
+	Middleware,
+	NextHandler,
+};
+
+/// A [`Service`] that runs requests through an [`App`], for embedding the
+/// app in other hyper based servers or wrapping it in tower layers. Created
+/// using [`App::into_service`].
+pub struct AppService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	app: Arc<App<TContext, TMiddleware, TState, TErrorData>>,
+	remote_addr: SocketAddr,
+}
+
+impl<TContext, TMiddleware, TState, TErrorData>
+	AppService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	pub(crate) fn new(
+		app: App<TContext, TMiddleware, TState, TErrorData>,
+	) -> Self {
+		AppService {
+			app: Arc::new(app),
+			remote_addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+		}
+	}
+
+	/// Sets the socket address given to requests handled by this service,
+	/// which defaults to `0.0.0.0:0` as the service can't know where a
+	/// request came from
+	pub fn with_remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+		self.remote_addr = remote_addr;
+		self
+	}
+}
+
+impl<TContext, TMiddleware, TState, TErrorData> Clone
+	for AppService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	fn clone(&self) -> Self {
+		AppService {
+			app: self.app.clone(),
+			remote_addr: self.remote_addr,
+		}
+	}
+}
+
+impl<TContext, TMiddleware, TState, TErrorData> Service<HyperRequest<Body>>
+	for AppService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TMiddleware:
+		'static + Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TState: 'static + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+{
+	type Response = HyperResponse<Body>;
+	type Error = HyperError;
+	type Future = Pin<
+		Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>,
+	>;
+
+	fn poll_ready(
+		&mut self,
+		_: &mut PollContext<'_>,
+	) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, req: HyperRequest<Body>) -> Self::Future {
+		Box::pin(handle_request(self.app.clone(), self.remote_addr, req))
+	}
+}
+
+/// Creates an [`AppService`] for every connection accepted by a hyper
+/// server, using the address of the connection as the socket address of its
+/// requests. Created using [`App::into_make_service`].
+pub struct AppMakeService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	service: AppService<TContext, TMiddleware, TState, TErrorData>,
+}
+
+impl<TContext, TMiddleware, TState, TErrorData>
+	AppMakeService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	pub(crate) fn new(
+		app: App<TContext, TMiddleware, TState, TErrorData>,
+	) -> Self {
+		AppMakeService {
+			service: AppService::new(app),
+		}
+	}
+}
+
+impl<TContext, TMiddleware, TState, TErrorData> Clone
+	for AppMakeService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	fn clone(&self) -> Self {
+		AppMakeService {
+			service: self.service.clone(),
+		}
+	}
+}
+
+impl<'a, TContext, TMiddleware, TState, TErrorData> Service<&'a AddrStream>
+	for AppMakeService<TContext, TMiddleware, TState, TErrorData>
+where
+	TContext: Context + Debug + Send + Sync,
+	TMiddleware: Middleware<TContext, TErrorData> + Clone + Send + Sync,
+	TErrorData: Default + Send + Sync,
+	TState: Send + Sync,
+{
+	type Response = AppService<TContext, TMiddleware, TState, TErrorData>;
+	type Error = Infallible;
+	type Future = Ready<Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(
+		&mut self,
+		_: &mut PollContext<'_>,
+	) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, conn: &'a AddrStream) -> Self::Future {
+		future::ready(Ok(self
+			.service
+			.clone()
+			.with_remote_addr(conn.remote_addr())))
+	}
+}
+
+/// A middleware that hands requests to a tower [`Service`] and sends its
+/// response. The middlewares after it aren't run, so it's usually the last
+/// middleware of a route.
+#[derive(Clone)]
+pub struct TowerService<TService> {
+	service: TService,
+}
+
+impl<TService> TowerService<TService> {
+	pub fn new(service: TService) -> Self {
+		TowerService { service }
+	}
+
+	pub async fn serve<TContext, TErrorData>(
+		&self,
+		mut context: TContext,
+	) -> Result<TContext, Error<TErrorData>>
+	where
+		TContext: Context + Debug + Send + Sync,
+		TErrorData: Default + Send + Sync,
+		TService: Service<HyperRequest<Body>> + Clone + Send,
+		TService::Response: ServiceResponse,
+		TService::Error: Into<BoxError>,
+		TService::Future: Send,
+	{
+		let request = context.get_request_mut().take_hyper_request()?;
+		let response =
+			call_service(&mut self.service.clone(), request)
+				.await
+				.map_err(|err| Error::<TErrorData>::new(err).status(500))?;
+		set_response(&mut context, response, false).await;
+		Ok(context)
+	}
+}
+
+#[async_trait::async_trait]
+impl<TContext, TErrorData, TService> Middleware<TContext, TErrorData>
+	for TowerService<TService>
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+	TService: Service<HyperRequest<Body>> + Clone + Send + Sync,
+	TService::Response: ServiceResponse,
+	TService::Error: Into<BoxError>,
+	TService::Future: Send,
+{
+	async fn run_middleware(
+		&self,
+		context: TContext,
+		_: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>> {
+		self.serve(context).await
+	}
+}
+
+/// A middleware that runs the rest of the middleware chain inside a tower
+/// [`Layer`], so that the layer can modify the request before the chain
+/// runs and the response after it.
+///
+/// If the layer responds without running the rest of the chain, its
+/// response is sent as usual. If it drops the rest of the chain while it's
+/// running, as a timeout would, its response is returned as an error with
+/// the same status and body, since the context is dropped along with the
+/// chain.
+#[derive(Clone)]
+pub struct TowerLayer<TLayer> {
+	layer: TLayer,
+}
+
+impl<TLayer> TowerLayer<TLayer> {
+	pub fn new(layer: TLayer) -> Self {
+		TowerLayer { layer }
+	}
+
+	pub async fn serve<TContext, TErrorData>(
+		&self,
+		mut context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>>
+	where
+		TContext: 'static + Context + Debug + Send + Sync,
+		TErrorData: 'static + Default + Send + Sync,
+		TLayer: Layer<NextService<TContext, TErrorData>>,
+		TLayer::Service: Service<HyperRequest<Body>> + Send,
+		<TLayer::Service as Service<HyperRequest<Body>>>::Response:
+			ServiceResponse,
+		<TLayer::Service as Service<HyperRequest<Body>>>::Error: Into<BoxError>,
+		<TLayer::Service as Service<HyperRequest<Body>>>::Future: Send,
+	{
+		let request = context.get_request_mut().take_hyper_request()?;
+		let state = Arc::new(Mutex::new(NextState::Ready(context, next)));
+		let mut service = self.layer.layer(NextService {
+			state: state.clone(),
+		});
+		let result = call_service(&mut service, request).await;
+
+		let state = mem::replace(&mut *state.lock().unwrap(), NextState::Ran);
+		match (state, result) {
+			// The error is handled as though there was no layer
+			(NextState::Done(Err(err)), _) => Err(err),
+			(
+				NextState::Ready(mut context, _) |
+				NextState::Done(Ok(mut context)),
+				Ok(response),
+			) => {
+				set_response(&mut context, response, true).await;
+				Ok(context)
+			}
+			(NextState::Ran, Ok(response)) => {
+				let status = response.status().as_u16();
+				let body = body::to_bytes(response.into_body())
+					.await
+					.unwrap_or_default();
+				Err(Error::<TErrorData>::empty()
+					.status(status)
+					.body_bytes(&body))
+			}
+			(_, Err(err)) => Err(Error::<TErrorData>::new(err).status(500)),
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl<TContext, TErrorData, TLayer> Middleware<TContext, TErrorData>
+	for TowerLayer<TLayer>
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+	TLayer: Layer<NextService<TContext, TErrorData>> + Send + Sync,
+	TLayer::Service: Service<HyperRequest<Body>> + Send,
+	<TLayer::Service as Service<HyperRequest<Body>>>::Response: ServiceResponse,
+	<TLayer::Service as Service<HyperRequest<Body>>>::Error: Into<BoxError>,
+	<TLayer::Service as Service<HyperRequest<Body>>>::Future: Send,
+{
+	async fn run_middleware(
+		&self,
+		context: TContext,
+		next: NextHandler<TContext, TErrorData>,
+	) -> Result<TContext, Error<TErrorData>> {
+		self.serve(context, next).await
+	}
+}
+
+enum NextState<TContext, TErrorData>
+where
+	TErrorData: Default,
+{
+	/// The rest of the chain hasn't run yet
+	Ready(TContext, NextHandler<TContext, TErrorData>),
+	/// The rest of the chain is running or has been dropped while running
+	Ran,
+	/// The rest of the chain has finished running
+	Done(Result<TContext, Error<TErrorData>>),
+}
+
+/// The service wrapped by a [`TowerLayer`], which runs the rest of the
+/// middleware chain with the request given to it by the layer
+pub struct NextService<TContext, TErrorData>
+where
+	TErrorData: Default,
+{
+	state: Arc<Mutex<NextState<TContext, TErrorData>>>,
+}
+
+impl<TContext, TErrorData> Clone for NextService<TContext, TErrorData>
+where
+	TErrorData: Default,
+{
+	fn clone(&self) -> Self {
+		NextService {
+			state: self.state.clone(),
+		}
+	}
+}
+
+impl<TContext, TErrorData, TBody> Service<HyperRequest<TBody>>
+	for NextService<TContext, TErrorData>
+where
+	TContext: 'static + Context + Debug + Send + Sync,
+	TErrorData: 'static + Default + Send + Sync,
+	TBody: HttpBody + Send + 'static,
+	TBody::Data: Send,
+	TBody::Error: Into<BoxError>,
+{
+	type Response = HyperResponse<Body>;
+	type Error = BoxError;
+	type Future = Pin<
+		Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>,
+	>;
+
+	fn poll_ready(
+		&mut self,
+		_: &mut PollContext<'_>,
+	) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, req: HyperRequest<TBody>) -> Self::Future {
+		let state = self.state.clone();
+		Box::pin(async move {
+			let (mut context, next) = {
+				let mut state = state.lock().unwrap();
+				match mem::replace(&mut *state, NextState::Ran) {
+					NextState::Ready(context, next) => (context, next),
+					previous => {
+						*state = previous;
+						return Err(
+							"the rest of the middleware chain has already run"
+								.into(),
+						);
+					}
+				}
+			};
+
+			context
+				.get_request_mut()
+				.set_hyper_request(req.map(into_body));
+			match next(context).await {
+				Ok(mut context) => {
+					let response = mem::take(context.get_response_mut());
+					// The WebSocket handler is started by the app once the
+					// context is returned
+					#[cfg(feature = "websocket")]
+					{
+						context.get_response_mut().websocket_handler =
+							response.websocket_handler;
+					}
+					*state.lock().unwrap() = NextState::Done(Ok(context));
+					response.into_hyper_response().map_err(Into::into)
+				}
+				Err(err) => {
+					*state.lock().unwrap() = NextState::Done(Err(err));
+					Err("the middleware chain returned an error".into())
+				}
+			}
+		})
+	}
+}
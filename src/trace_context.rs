@@ -0,0 +1,290 @@
+use std::fmt::Debug;
+
+use tracing::{field, Span};
+use uuid::Uuid;
+
+use crate::{request::HyperRequest, Context, Error};
+
+const TRACEPARENT_HEADER: &str = "traceparent";
+const TRACESTATE_HEADER: &str = "tracestate";
+const SAMPLED_FLAG: u8 = 0x01;
+
+/// The W3C trace context of a request. If the request has a valid
+/// `traceparent` header, its trace is continued. Otherwise, a new trace is
+/// started.
+///
+/// The IDs are only recorded as fields of the request span. The span isn't
+/// linked to the parent span of an OpenTelemetry pipeline, so a subscriber
+/// that exports spans has to read the `trace_id` and `parent_id` fields to
+/// join them up.
+#[derive(Clone, Debug)]
+pub struct TraceContext {
+	trace_id: String,
+	parent_id: Option<String>,
+	span_id: String,
+	flags: u8,
+	trace_state: Option<String>,
+}
+
+impl TraceContext {
+	pub(crate) fn from_hyper(request: &HyperRequest) -> Self {
+		let get_header = |name| {
+			request
+				.headers()
+				.get(name)
+				.and_then(|value| value.to_str().ok())
+				.map(str::to_string)
+		};
+		let span_id = generate_id(16);
+		match get_header(TRACEPARENT_HEADER)
+			.and_then(|header| parse_traceparent(&header))
+		{
+			Some((trace_id, parent_id, flags)) => TraceContext {
+				trace_id,
+				parent_id: Some(parent_id),
+				span_id,
+				flags,
+				trace_state: get_header(TRACESTATE_HEADER),
+			},
+			None => TraceContext {
+				trace_id: generate_id(32),
+				parent_id: None,
+				span_id,
+				flags: SAMPLED_FLAG,
+				trace_state: None,
+			},
+		}
+	}
+
+	pub fn get_trace_id(&self) -> &str {
+		&self.trace_id
+	}
+
+	/// Returns the ID of the span in the caller that this request was made
+	/// from, if the request continued an existing trace
+	pub fn get_parent_id(&self) -> Option<&str> {
+		self.parent_id.as_deref()
+	}
+
+	/// Returns the ID of the span handling this request
+	pub fn get_span_id(&self) -> &str {
+		&self.span_id
+	}
+
+	pub fn is_sampled(&self) -> bool {
+		self.flags & SAMPLED_FLAG != 0
+	}
+
+	pub fn get_trace_state(&self) -> Option<&str> {
+		self.trace_state.as_deref()
+	}
+
+	/// Returns the `traceparent` header to send on requests made while
+	/// handling this request, so that the services they reach join the trace
+	pub fn to_traceparent(&self) -> String {
+		format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+	}
+
+	/// Returns the `traceparent` and `tracestate` headers to send on requests
+	/// made while handling this request
+	pub fn get_headers(&self) -> Vec<(&'static str, String)> {
+		let mut headers = vec![(TRACEPARENT_HEADER, self.to_traceparent())];
+		if let Some(trace_state) = &self.trace_state {
+			headers.push((TRACESTATE_HEADER, trace_state.clone()));
+		}
+		headers
+	}
+}
+
+/// Creates the span that the whole middleware chain of a request runs in.
+/// The status is recorded once the chain completes. The trace context is
+/// recorded as fields rather than as the parent of the span, as `tracing`
+/// has no notion of remote parents.
+pub(crate) fn request_span<TContext>(context: &TContext) -> Span
+where
+	TContext: Context + Debug + Send + Sync,
+{
+	let trace_context = context.get_request().get_trace_context();
+	tracing::info_span!(
+		"request",
+		method = %context.get_method(),
+		path = %context.get_path(),
+		route = context.get_route(),
+		status = field::Empty,
+		trace_id = trace_context.get_trace_id(),
+		span_id = trace_context.get_span_id(),
+		parent_id = trace_context.get_parent_id(),
+	)
+}
+
+pub(crate) fn record_status<TContext, TErrorData>(
+	span: &Span,
+	result: &Result<TContext, Error<TErrorData>>,
+) where
+	TContext: Context + Debug + Send + Sync,
+	TErrorData: Default + Send + Sync,
+{
+	let status = match result {
+		Ok(context) => context.get_response().get_status(),
+		Err(err) => err.get_status().unwrap_or(500),
+	};
+	span.record("status", status);
+}
+
+/// Sends the `traceparent` of the request span back on the response, so that
+/// the caller can find the span the request was handled in
+pub(crate) fn set_traceparent<TContext, TErrorData>(
+	traceparent: &str,
+	result: Result<TContext, Error<TErrorData>>,
+) -> Result<TContext, Error<TErrorData>>
+where
+	TContext: Context + Debug + Send + Sync,
+	TErrorData: Default + Send + Sync,
+{
+	match result {
+		Ok(mut context) => {
+			context.header(TRACEPARENT_HEADER, traceparent);
+			Ok(context)
+		}
+		Err(err) => Err(err.header(TRACEPARENT_HEADER, traceparent)),
+	}
+}
+
+/// Parses a `traceparent` header, such as
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`, into the trace
+/// ID, parent ID and flags
+fn parse_traceparent(header: &str) -> Option<(String, String, u8)> {
+	let fields = header.trim().split('-').collect::<Vec<_>>();
+	let (version, trace_id, parent_id, flags) = match fields.as_slice() {
+		[version, trace_id, parent_id, flags] => {
+			(*version, *trace_id, *parent_id, *flags)
+		}
+		// Later versions may add more fields, which can be ignored
+		[version, trace_id, parent_id, flags, ..] if *version != "00" => {
+			(*version, *trace_id, *parent_id, *flags)
+		}
+		_ => return None,
+	};
+
+	if !is_hex(version, 2) || version == "ff" {
+		return None;
+	}
+	// IDs made entirely of zeros are invalid
+	if !is_hex(trace_id, 32) || trace_id.bytes().all(|byte| byte == b'0') {
+		return None;
+	}
+	if !is_hex(parent_id, 16) || parent_id.bytes().all(|byte| byte == b'0') {
+		return None;
+	}
+	if !is_hex(flags, 2) {
+		return None;
+	}
+
+	Some((
+		trace_id.to_string(),
+		parent_id.to_string(),
+		u8::from_str_radix(flags, 16).ok()?,
+	))
+}
+
+fn is_hex(value: &str, length: usize) -> bool {
+	value.len() == length &&
+		value
+			.bytes()
+			.all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Generates a random ID of the given number of hex digits, up to 32
+fn generate_id(length: usize) -> String {
+	let mut id = Uuid::new_v4().simple().to_string();
+	id.truncate(length);
+	id
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{Body, Request};
+
+	use super::*;
+
+	const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+	const PARENT_ID: &str = "00f067aa0ba902b7";
+
+	fn traceparent(version: &str, trace_id: &str, parent_id: &str) -> String {
+		format!("{}-{}-{}-01", version, trace_id, parent_id)
+	}
+
+	#[test]
+	fn valid_traceparents_are_parsed() {
+		assert_eq!(
+			parse_traceparent(&traceparent("00", TRACE_ID, PARENT_ID)),
+			Some((TRACE_ID.to_string(), PARENT_ID.to_string(), 1))
+		);
+		assert_eq!(
+			parse_traceparent(&format!("00-{}-{}-00", TRACE_ID, PARENT_ID))
+				.map(|(_, _, flags)| flags),
+			Some(0)
+		);
+		// Later versions can add fields
+		assert!(parse_traceparent(&format!(
+			"{}-extra",
+			traceparent("01", TRACE_ID, PARENT_ID)
+		))
+		.is_some());
+	}
+
+	#[test]
+	fn invalid_traceparents_are_ignored() {
+		let invalid = [
+			traceparent("ff", TRACE_ID, PARENT_ID),
+			traceparent("0", TRACE_ID, PARENT_ID),
+			traceparent("00", &"0".repeat(32), PARENT_ID),
+			traceparent("00", TRACE_ID, &"0".repeat(16)),
+			traceparent("00", &TRACE_ID.to_uppercase(), PARENT_ID),
+			traceparent("00", &TRACE_ID[1..], PARENT_ID),
+			traceparent("00", TRACE_ID, "00f067aa0ba902bz"),
+			format!("{}-extra", traceparent("00", TRACE_ID, PARENT_ID)),
+			format!("00-{}-{}", TRACE_ID, PARENT_ID),
+			format!("00-{}-{}-1", TRACE_ID, PARENT_ID),
+		];
+		for header in invalid {
+			assert_eq!(parse_traceparent(&header), None, "{}", header);
+		}
+	}
+
+	#[test]
+	fn traces_are_continued_or_started() {
+		let request = Request::builder()
+			.header(TRACEPARENT_HEADER, traceparent("00", TRACE_ID, PARENT_ID))
+			.header(TRACESTATE_HEADER, "vendor=value")
+			.body(Body::empty())
+			.unwrap();
+		let context = TraceContext::from_hyper(&request);
+		assert_eq!(context.get_trace_id(), TRACE_ID);
+		assert_eq!(context.get_parent_id(), Some(PARENT_ID));
+		assert_ne!(context.get_span_id(), PARENT_ID);
+		assert!(context.is_sampled());
+		assert_eq!(
+			context.get_headers(),
+			[
+				(
+					TRACEPARENT_HEADER,
+					traceparent("00", TRACE_ID, context.get_span_id())
+				),
+				(TRACESTATE_HEADER, "vendor=value".to_string()),
+			]
+		);
+
+		// The trace state belongs to the trace it came with
+		let request = Request::builder()
+			.header(TRACEPARENT_HEADER, "invalid")
+			.header(TRACESTATE_HEADER, "vendor=value")
+			.body(Body::empty())
+			.unwrap();
+		let context = TraceContext::from_hyper(&request);
+		assert_eq!(context.get_parent_id(), None);
+		assert_eq!(context.get_trace_state(), None);
+		assert!(is_hex(context.get_trace_id(), 32));
+		assert!(is_hex(context.get_span_id(), 16));
+	}
+}
@@ -0,0 +1,401 @@
+use std::{
+	net::{IpAddr, SocketAddr},
+	str::FromStr,
+};
+
+use ipnet::{AddrParseError, IpNet};
+
+/// Which proxies in front of the app are trusted to report the client's
+/// address, protocol and host using the `Forwarded` or `X-Forwarded-*`
+/// headers
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum TrustedProxies {
+	/// Forwarding headers are ignored and the socket peer is the client
+	#[default]
+	None,
+	/// Every proxy whose address is within one of the ranges is trusted
+	Cidrs(Vec<IpNet>),
+	/// The given number of proxies nearest to the app are trusted,
+	/// regardless of their address
+	HopCount(usize),
+}
+
+impl TrustedProxies {
+	/// Creates a list of trusted ranges from strings such as `10.0.0.0/8`.
+	/// Plain addresses are treated as ranges with a single address.
+	pub fn from_cidrs(cidrs: &[&str]) -> Result<Self, AddrParseError> {
+		let ranges = cidrs
+			.iter()
+			.map(|cidr| {
+				IpNet::from_str(cidr).or_else(|err| {
+					IpAddr::from_str(cidr).map(IpNet::from).map_err(|_| err)
+				})
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(TrustedProxies::Cidrs(ranges))
+	}
+
+	fn is_trusted(&self, address: &IpAddr) -> bool {
+		match self {
+			TrustedProxies::None => false,
+			TrustedProxies::Cidrs(ranges) => {
+				ranges.iter().any(|range| range.contains(address))
+			}
+			TrustedProxies::HopCount(hops) => *hops > 0,
+		}
+	}
+}
+
+/// Which forwarding headers the trusted proxies set. Only the headers of
+/// that family are read, as a proxy passes on the headers it doesn't set
+/// as they were sent by the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ForwardedHeaders {
+	/// The `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+	/// headers. Each proxy is expected to append to every header it sets,
+	/// so that the values line up with the addresses from the right.
+	#[default]
+	XForwarded,
+	/// The `Forwarded` header defined in RFC 7239
+	Forwarded,
+}
+
+/// The client information reported by trusted proxies
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ForwardedInfo {
+	pub(crate) ip: Option<IpAddr>,
+	pub(crate) protocol: Option<String>,
+	pub(crate) host: Option<String>,
+}
+
+/// What a proxy reported about the hop it received the request from
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Hop {
+	address: Option<IpAddr>,
+	protocol: Option<String>,
+	host: Option<String>,
+}
+
+/// Works out the client information from the forwarding headers, trusting
+/// only the proxies allowed by the configuration. `forwarded` and the
+/// `x_forwarded_*` values are the raw header values, if present.
+pub(crate) fn resolve(
+	trusted_proxies: &TrustedProxies,
+	forwarded_headers: ForwardedHeaders,
+	peer: IpAddr,
+	forwarded: Option<String>,
+	x_forwarded_for: Option<String>,
+	x_forwarded_proto: Option<String>,
+	x_forwarded_host: Option<String>,
+) -> ForwardedInfo {
+	if !trusted_proxies.is_trusted(&peer) {
+		return ForwardedInfo::default();
+	}
+
+	let mut hops = match forwarded_headers {
+		ForwardedHeaders::Forwarded => {
+			parse_forwarded(&forwarded.unwrap_or_default())
+		}
+		ForwardedHeaders::XForwarded => parse_x_forwarded(
+			x_forwarded_for,
+			x_forwarded_proto,
+			x_forwarded_host,
+		),
+	};
+	hops.push(Hop {
+		address: Some(peer),
+		..Default::default()
+	});
+
+	// The chain of hops goes from the client to the nearest proxy, so walk
+	// backwards from the socket peer until an untrusted hop is found
+	let mut client = hops.len() - 1;
+	while client > 0 {
+		let trusted = match trusted_proxies {
+			TrustedProxies::HopCount(count) => hops.len() - client <= *count,
+			_ => hops[client]
+				.address
+				.as_ref()
+				.map(|address| trusted_proxies.is_trusted(address))
+				.unwrap_or(false),
+		};
+		if !trusted || hops[client - 1].address.is_none() {
+			break;
+		}
+		client -= 1;
+	}
+
+	// The protocol and host are only taken from the hop that was reported
+	// by the last trusted proxy, never from the hops before it
+	let hop = hops.swap_remove(client);
+	ForwardedInfo {
+		ip: hop.address,
+		protocol: hop.protocol.map(|protocol| protocol.to_lowercase()),
+		host: hop.host,
+	}
+}
+
+fn split_list(value: Option<String>) -> Vec<String> {
+	value
+		.map(|value| {
+			value
+				.split(',')
+				.map(str::trim)
+				.filter(|item| !item.is_empty())
+				.map(String::from)
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+/// Lines the values of the `X-Forwarded-*` headers up with the addresses
+/// from the right, as the values nearest to the app were appended by the
+/// nearest proxy
+fn parse_x_forwarded(
+	x_forwarded_for: Option<String>,
+	x_forwarded_proto: Option<String>,
+	x_forwarded_host: Option<String>,
+) -> Vec<Hop> {
+	let addresses = split_list(x_forwarded_for);
+	let protocols = split_list(x_forwarded_proto);
+	let hosts = split_list(x_forwarded_host);
+	let value_at = |values: &[String], index: usize| {
+		(values.len() + index)
+			.checked_sub(addresses.len())
+			.and_then(|index| values.get(index))
+			.cloned()
+	};
+
+	addresses
+		.iter()
+		.enumerate()
+		.map(|(index, address)| Hop {
+			address: parse_address(address),
+			protocol: value_at(&protocols, index),
+			host: value_at(&hosts, index),
+		})
+		.collect()
+}
+
+/// Parses the `for`, `proto` and `host` parameters of every element of a
+/// `Forwarded` header, as defined in RFC 7239
+fn parse_forwarded(value: &str) -> Vec<Hop> {
+	value
+		.split(',')
+		.filter(|element| !element.trim().is_empty())
+		.map(|element| {
+			let mut hop = Hop::default();
+			for pair in element.split(';') {
+				let mut pieces = pair.splitn(2, '=');
+				let key = pieces.next().unwrap_or("").trim().to_lowercase();
+				let value =
+					pieces.next().unwrap_or("").trim().trim_matches('"');
+				if value.is_empty() {
+					continue;
+				}
+				match key.as_str() {
+					"for" => hop.address = parse_address(value),
+					"proto" => hop.protocol = Some(value.to_string()),
+					"host" => hop.host = Some(value.to_string()),
+					_ => (),
+				}
+			}
+			hop
+		})
+		.collect()
+}
+
+/// Parses an address that may have a port and may be wrapped in brackets,
+/// such as `192.0.2.60`, `192.0.2.60:4711` or `[2001:db8::17]:4711`
+fn parse_address(address: &str) -> Option<IpAddr> {
+	IpAddr::from_str(address)
+		.ok()
+		.or_else(|| SocketAddr::from_str(address).ok().map(|addr| addr.ip()))
+		.or_else(|| {
+			IpAddr::from_str(
+				address.trim_start_matches('[').trim_end_matches(']'),
+			)
+			.ok()
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PEER: &str = "10.0.0.1";
+
+	fn resolve_for(
+		trusted_proxies: &TrustedProxies,
+		x_forwarded_for: &str,
+	) -> ForwardedInfo {
+		resolve(
+			trusted_proxies,
+			ForwardedHeaders::XForwarded,
+			PEER.parse().unwrap(),
+			None,
+			Some(x_forwarded_for.to_string()),
+			Some("HTTPS".to_string()),
+			Some("example.com".to_string()),
+		)
+	}
+
+	fn resolve_forwarded(
+		trusted_proxies: &TrustedProxies,
+		forwarded: &str,
+	) -> ForwardedInfo {
+		resolve(
+			trusted_proxies,
+			ForwardedHeaders::Forwarded,
+			PEER.parse().unwrap(),
+			Some(forwarded.to_string()),
+			Some("1.1.1.1".to_string()),
+			Some("https".to_string()),
+			Some("example.com".to_string()),
+		)
+	}
+
+	fn ip(address: &str) -> Option<IpAddr> {
+		Some(address.parse().unwrap())
+	}
+
+	#[test]
+	fn untrusted_peers_are_the_client() {
+		let info = resolve_for(&TrustedProxies::None, "1.1.1.1");
+		assert_eq!(info.ip, None);
+		assert_eq!(info.protocol, None);
+
+		let trusted = TrustedProxies::from_cidrs(&["192.168.0.0/16"]).unwrap();
+		let info = resolve_for(&trusted, "1.1.1.1");
+		assert_eq!(info.ip, None);
+	}
+
+	#[test]
+	fn trusted_hops_are_skipped() {
+		let trusted = TrustedProxies::from_cidrs(&["10.0.0.0/8"]).unwrap();
+		let info = resolve_for(&trusted, "203.0.113.5");
+		assert_eq!(info.ip, ip("203.0.113.5"));
+		assert_eq!(info.protocol.as_deref(), Some("https"));
+		assert_eq!(info.host.as_deref(), Some("example.com"));
+
+		let info = resolve_for(&trusted, "203.0.113.5, 10.0.0.2");
+		assert_eq!(info.ip, ip("203.0.113.5"));
+
+		// Addresses before the first untrusted hop could be spoofed
+		let info = resolve_for(&trusted, "1.1.1.1, 203.0.113.5");
+		assert_eq!(info.ip, ip("203.0.113.5"));
+	}
+
+	#[test]
+	fn x_forwarded_values_line_up_from_the_right() {
+		let trusted = TrustedProxies::from_cidrs(&["10.0.0.0/8"]).unwrap();
+		let info = resolve(
+			&trusted,
+			ForwardedHeaders::XForwarded,
+			PEER.parse().unwrap(),
+			None,
+			Some("203.0.113.5, 10.0.0.2".to_string()),
+			Some("https, http".to_string()),
+			Some("example.com".to_string()),
+		);
+		assert_eq!(info.ip, ip("203.0.113.5"));
+		assert_eq!(info.protocol.as_deref(), Some("https"));
+		// The only host was set for the hop between the proxies
+		assert_eq!(info.host, None);
+	}
+
+	#[test]
+	fn hop_counts_trust_the_nearest_proxies() {
+		let info =
+			resolve_for(&TrustedProxies::HopCount(1), "1.1.1.1, 203.0.113.5");
+		assert_eq!(info.ip, ip("203.0.113.5"));
+
+		let info =
+			resolve_for(&TrustedProxies::HopCount(2), "1.1.1.1, 203.0.113.5");
+		assert_eq!(info.ip, ip("1.1.1.1"));
+
+		// Invalid addresses stop the walk
+		let info =
+			resolve_for(&TrustedProxies::HopCount(5), "unknown, 203.0.113.5");
+		assert_eq!(info.ip, ip("203.0.113.5"));
+	}
+
+	#[test]
+	fn only_the_trusted_header_family_is_read() {
+		let trusted =
+			TrustedProxies::from_cidrs(&["10.0.0.0/8", "2001:db8::/32"])
+				.unwrap();
+		let info = resolve_forwarded(
+			&trusted,
+			"for=192.0.2.60;proto=HTTP;host=app.example.com, \
+			 for=\"[2001:db8::17]:4711\"",
+		);
+		assert_eq!(info.ip, ip("192.0.2.60"));
+		assert_eq!(info.protocol.as_deref(), Some("http"));
+		assert_eq!(info.host.as_deref(), Some("app.example.com"));
+
+		let info = resolve(
+			&trusted,
+			ForwardedHeaders::XForwarded,
+			PEER.parse().unwrap(),
+			Some("for=192.0.2.60;host=app.example.com".to_string()),
+			Some("203.0.113.5".to_string()),
+			None,
+			None,
+		);
+		assert_eq!(info.ip, ip("203.0.113.5"));
+		assert_eq!(info.host, None);
+	}
+
+	#[test]
+	fn client_injected_forwarded_headers_are_ignored() {
+		let trusted = TrustedProxies::from_cidrs(&["10.0.0.0/8"]).unwrap();
+		// The client sent the first element and the proxy appended the second
+		let info = resolve_forwarded(
+			&trusted,
+			"for=1.1.1.1;proto=http;host=evil.example.com, \
+			 for=203.0.113.5;proto=https;host=app.example.com",
+		);
+		assert_eq!(info.ip, ip("203.0.113.5"));
+		assert_eq!(info.protocol.as_deref(), Some("https"));
+		assert_eq!(info.host.as_deref(), Some("app.example.com"));
+	}
+
+	#[test]
+	fn proxies_that_omit_the_host_report_no_host() {
+		let trusted = TrustedProxies::from_cidrs(&["10.0.0.0/8"]).unwrap();
+		let info = resolve_forwarded(
+			&trusted,
+			"for=1.1.1.1;host=evil.example.com, for=203.0.113.5",
+		);
+		assert_eq!(info.ip, ip("203.0.113.5"));
+		assert_eq!(info.protocol, None);
+		assert_eq!(info.host, None);
+
+		// Elements without an address don't shift the other elements
+		let info = resolve_forwarded(
+			&trusted,
+			"for=203.0.113.5;host=evil.example.com, proto=https",
+		);
+		assert_eq!(info.ip, ip(PEER));
+		assert_eq!(info.protocol, None);
+		assert_eq!(info.host, None);
+	}
+
+	#[test]
+	fn addresses_are_parsed_with_ports_and_brackets() {
+		assert_eq!(parse_address("192.0.2.60"), ip("192.0.2.60"));
+		assert_eq!(parse_address("192.0.2.60:4711"), ip("192.0.2.60"));
+		assert_eq!(parse_address("[2001:db8::17]:4711"), ip("2001:db8::17"));
+		assert_eq!(parse_address("[2001:db8::17]"), ip("2001:db8::17"));
+		assert_eq!(parse_address("_hidden"), None);
+	}
+
+	#[test]
+	fn plain_addresses_are_single_address_ranges() {
+		let trusted = TrustedProxies::from_cidrs(&["10.0.0.1"]).unwrap();
+		assert!(trusted.is_trusted(&PEER.parse().unwrap()));
+		assert!(!trusted.is_trusted(&"10.0.0.2".parse().unwrap()));
+		assert!(TrustedProxies::from_cidrs(&["not an address"]).is_err());
+	}
+}
@@ -0,0 +1,238 @@
+use std::{
+	fmt::Debug,
+	future::Future,
+	pin::Pin,
+	task::{Context as PollContext, Poll},
+};
+
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use hyper::upgrade::Upgraded;
+use regex::Regex;
+pub use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{
+	tungstenite::{
+		handshake::derive_accept_key,
+		protocol::{frame::coding::CloseCode, CloseFrame, Role},
+		Error as WebSocketError,
+	},
+	WebSocketStream,
+};
+
+use crate::{middleware_handler::get_path_match, Context};
+
+pub type WebSocketHandlerFn<TContext> =
+	fn(TContext, WebSocket) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+pub(crate) struct WebSocketHandler<TContext>
+where
+	TContext: Context + Debug + Send + Sync,
+{
+	pub(crate) mounted_url: String,
+	pub(crate) path_match: Regex,
+	pub(crate) handler: WebSocketHandlerFn<TContext>,
+}
+
+impl<TContext> WebSocketHandler<TContext>
+where
+	TContext: Context + Debug + Send + Sync,
+{
+	pub(crate) fn new(
+		path: &str,
+		handler: WebSocketHandlerFn<TContext>,
+	) -> Self {
+		let (mounted_url, path_match) = get_path_match(path, true);
+		WebSocketHandler {
+			mounted_url,
+			path_match,
+			handler,
+		}
+	}
+
+	pub(crate) fn is_match(&self, url: &str) -> bool {
+		self.path_match.is_match(url)
+	}
+}
+
+impl<TContext> Clone for WebSocketHandler<TContext>
+where
+	TContext: Context + Debug + Send + Sync,
+{
+	fn clone(&self) -> Self {
+		WebSocketHandler {
+			mounted_url: self.mounted_url.clone(),
+			path_match: self.path_match.clone(),
+			handler: self.handler,
+		}
+	}
+}
+
+/// Returns true if the request asks for its connection to be upgraded to a
+/// WebSocket
+pub(crate) fn is_websocket_request<TContext>(context: &TContext) -> bool
+where
+	TContext: Context + Debug + Send + Sync,
+{
+	context
+		.get_header("Upgrade")
+		.map(|upgrade| {
+			upgrade
+				.split(',')
+				.any(|value| value.trim().eq_ignore_ascii_case("websocket"))
+		})
+		.unwrap_or(false)
+}
+
+/// Validates the WebSocket handshake and sets up the response to switch
+/// protocols. Returns false if the request isn't a valid handshake, in which
+/// case the client is told that an upgrade is required.
+pub(crate) fn accept_handshake<TContext>(context: &mut TContext) -> bool
+where
+	TContext: Context + Debug + Send + Sync,
+{
+	let connection_upgrade = context
+		.get_header("Connection")
+		.map(|connection| {
+			connection
+				.split(',')
+				.any(|value| value.trim().eq_ignore_ascii_case("upgrade"))
+		})
+		.unwrap_or(false);
+	let version = context.get_header("Sec-WebSocket-Version");
+	let key = context.get_header("Sec-WebSocket-Key");
+
+	match (key, version) {
+		(Some(key), Some(version))
+			if connection_upgrade &&
+				is_websocket_request(context) &&
+				version.trim() == "13" =>
+		{
+			context
+				.status(101)
+				.header("Upgrade", "websocket")
+				.header("Connection", "Upgrade")
+				.header(
+					"Sec-WebSocket-Accept",
+					&derive_accept_key(key.trim().as_bytes()),
+				);
+			true
+		}
+		_ => {
+			context
+				.status(426)
+				.header("Upgrade", "websocket")
+				.header("Sec-WebSocket-Version", "13")
+				.body("Upgrade required");
+			false
+		}
+	}
+}
+
+/// A WebSocket connection, handed to the handler of a WebSocket route once
+/// the handshake is complete. It can be used as a [`Stream`] of incoming
+/// messages and a [`Sink`] of outgoing messages. Pings are answered
+/// automatically while the connection is being read from, and close frames
+/// sent by the client are acknowledged.
+pub struct WebSocket {
+	inner: WebSocketStream<Upgraded>,
+}
+
+impl WebSocket {
+	pub(crate) async fn from_upgraded(upgraded: Upgraded) -> Self {
+		WebSocket {
+			inner: WebSocketStream::from_raw_socket(
+				upgraded,
+				Role::Server,
+				None,
+			)
+			.await,
+		}
+	}
+
+	/// Waits for the next message from the client. Returns `None` once the
+	/// connection is closed.
+	pub async fn recv(&mut self) -> Option<Result<Message, WebSocketError>> {
+		self.inner.next().await
+	}
+
+	pub async fn send(
+		&mut self,
+		message: Message,
+	) -> Result<(), WebSocketError> {
+		self.inner.send(message).await
+	}
+
+	pub async fn send_text(
+		&mut self,
+		text: &str,
+	) -> Result<(), WebSocketError> {
+		self.send(Message::Text(text.to_string())).await
+	}
+
+	pub async fn send_binary(
+		&mut self,
+		data: &[u8],
+	) -> Result<(), WebSocketError> {
+		self.send(Message::Binary(data.to_vec())).await
+	}
+
+	pub async fn ping(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+		self.send(Message::Ping(data.to_vec())).await
+	}
+
+	/// Starts the closing handshake with the given code and reason
+	pub async fn close(
+		&mut self,
+		code: u16,
+		reason: &str,
+	) -> Result<(), WebSocketError> {
+		self.inner
+			.close(Some(CloseFrame {
+				code: CloseCode::from(code),
+				reason: reason.to_string().into(),
+			}))
+			.await
+	}
+}
+
+impl Stream for WebSocket {
+	type Item = Result<Message, WebSocketError>;
+
+	fn poll_next(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+	) -> Poll<Option<Self::Item>> {
+		Pin::new(&mut self.inner).poll_next(cx)
+	}
+}
+
+impl Sink<Message> for WebSocket {
+	type Error = WebSocketError;
+
+	fn poll_ready(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+	) -> Poll<Result<(), Self::Error>> {
+		Pin::new(&mut self.inner).poll_ready(cx)
+	}
+
+	fn start_send(
+		mut self: Pin<&mut Self>,
+		item: Message,
+	) -> Result<(), Self::Error> {
+		Pin::new(&mut self.inner).start_send(item)
+	}
+
+	fn poll_flush(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+	) -> Poll<Result<(), Self::Error>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_close(
+		mut self: Pin<&mut Self>,
+		cx: &mut PollContext<'_>,
+	) -> Poll<Result<(), Self::Error>> {
+		Pin::new(&mut self.inner).poll_close(cx)
+	}
+}
@@ -0,0 +1,474 @@
+use std::{
+	convert::Infallible,
+	future::Future,
+	pin::Pin,
+	sync::Arc,
+	time::Duration,
+};
+
+use eve_rs::{
+	default_context_generator,
+	default_middlewares::{
+		request_id::request_id,
+		server_timing::server_timing,
+	},
+	handlebars::Handlebars,
+	App,
+	Context,
+	DefaultContext,
+	DefaultError,
+	DefaultMiddleware,
+	Error,
+	Middleware,
+	NextHandler,
+	RenderEngine,
+	Request,
+	Response,
+	TestClient,
+};
+use futures::stream;
+use hyper::{service::service_fn, Body, Response as HyperResponse};
+use serde_json::json;
+
+type TestApp = App<DefaultContext, DefaultMiddleware<()>, (), ()>;
+
+fn create_app() -> TestApp {
+	App::create(default_context_generator, ())
+}
+
+#[tokio::test]
+async fn upgrades_name_a_single_protocol() {
+	let mut app = create_app();
+	app.get(
+		"/any",
+		[DefaultMiddleware::new(|mut context, _| {
+			Box::pin(async move {
+				context.accept_upgrade();
+				Ok(context)
+			})
+		})],
+	);
+	app.get(
+		"/websocket",
+		[DefaultMiddleware::new(|mut context, _| {
+			Box::pin(async move {
+				if context.accept_upgrade_to(&["websocket"]).is_none() {
+					context.status(400);
+				}
+				Ok(context)
+			})
+		})],
+	);
+	let client = TestClient::new(app);
+
+	client
+		.get("/any")
+		.header("Connection", "Upgrade")
+		.header("Upgrade", "h2c, websocket")
+		.send()
+		.await
+		.assert_status(101)
+		.assert_header("Upgrade", "h2c");
+	client
+		.get("/websocket")
+		.header("Connection", "Upgrade")
+		.header("Upgrade", "h2c, WebSocket")
+		.send()
+		.await
+		.assert_status(101)
+		.assert_header("Upgrade", "WebSocket");
+	client
+		.get("/websocket")
+		.header("Connection", "Upgrade")
+		.header("Upgrade", "h2c")
+		.send()
+		.await
+		.assert_status(400);
+}
+
+#[tokio::test]
+async fn small_service_bodies_are_buffered() {
+	let mut app = create_app();
+	app.use_middleware(
+		"/",
+		[DefaultMiddleware::new(|context, next| {
+			Box::pin(async move {
+				let mut context = next(context).await?;
+				let is_streaming = context.get_response().is_streaming();
+				context.header("X-Streaming", &is_streaming.to_string());
+				Ok(context)
+			})
+		})],
+	);
+	app.mount_service(
+		"/small",
+		service_fn(|_| async {
+			Ok::<_, Infallible>(HyperResponse::new(Body::from("small")))
+		}),
+	);
+	app.mount_service(
+		"/streamed",
+		service_fn(|_| async {
+			let chunks = vec![Ok::<_, Infallible>("stream"), Ok("ed")];
+			Ok::<_, Infallible>(HyperResponse::new(Body::wrap_stream(
+				stream::iter(chunks),
+			)))
+		}),
+	);
+	let client = TestClient::new(app);
+
+	client
+		.get("/small")
+		.send()
+		.await
+		.assert_status(200)
+		.assert_header("X-Streaming", "false")
+		.assert_body("small");
+	client
+		.get("/streamed")
+		.send()
+		.await
+		.assert_status(200)
+		.assert_header("X-Streaming", "true")
+		.assert_body("streamed");
+}
+
+#[tokio::test]
+async fn the_first_matching_route_is_the_route_pattern() {
+	let mut app = create_app();
+	let respond_with_route = || {
+		DefaultMiddleware::new(|mut context, _| {
+			Box::pin(async move {
+				let route = context.get_route().unwrap_or_default().to_string();
+				context.body(&route);
+				Ok(context)
+			})
+		})
+	};
+	app.get("/users/me", [respond_with_route()]);
+	app.get("/users/:id", [respond_with_route()]);
+	let client = TestClient::new(app);
+
+	client
+		.get("/users/me")
+		.send()
+		.await
+		.assert_body("/users/me");
+	client
+		.get("/users/42")
+		.send()
+		.await
+		.assert_body("/users/:id");
+}
+
+#[tokio::test]
+async fn request_ids_are_echoed_on_errors() {
+	let mut app = create_app();
+	app.use_middleware(
+		"/",
+		[DefaultMiddleware::new(|context, next| {
+			Box::pin(async move { request_id().run(context, next).await })
+		})],
+	);
+	app.get(
+		"/fail",
+		[DefaultMiddleware::new(|_, _| {
+			Box::pin(async move { Err(DefaultError::empty().status(503)) })
+		})],
+	);
+	let client = TestClient::new(app);
+
+	client
+		.get("/fail")
+		.header("X-Request-Id", "abc-123")
+		.send()
+		.await
+		.assert_status(503)
+		.assert_header("X-Request-Id", "abc-123");
+}
+
+#[tokio::test]
+async fn server_timings_are_sent_on_errors() {
+	let mut app = create_app();
+	app.use_middleware(
+		"/",
+		[DefaultMiddleware::new(|context, next| {
+			Box::pin(async move {
+				server_timing()
+					.middleware_timings(true)
+					.run(context, next)
+					.await
+			})
+		})],
+	);
+	app.get(
+		"/ok",
+		[DefaultMiddleware::new(|mut context, _| {
+			Box::pin(async move {
+				context.timing("db", Duration::from_millis(5));
+				Ok(context)
+			})
+		})],
+	);
+	app.get(
+		"/fail",
+		[DefaultMiddleware::new(|_, _| {
+			Box::pin(async move { Err(DefaultError::empty().status(500)) })
+		})],
+	);
+	let client = TestClient::new(app);
+
+	let response = client.get("/ok").send().await;
+	let header = response
+		.get_response()
+		.get_header("Server-Timing")
+		.unwrap_or_default();
+	assert!(header.starts_with("db;dur=5.000, middleware-1;desc=\"/ok\";dur="));
+	assert!(header.contains(", total;dur="));
+
+	let response = client.get("/fail").send().await;
+	response.assert_status(500);
+	let header = response
+		.get_response()
+		.get_header("Server-Timing")
+		.unwrap_or_default();
+	assert!(header.starts_with("total;dur="));
+}
+
+#[tokio::test]
+async fn invalid_responses_are_internal_server_errors() {
+	let mut app = create_app();
+	app.get(
+		"/invalid",
+		[DefaultMiddleware::new(|mut context, _| {
+			Box::pin(async move {
+				context.header("X-Invalid", "line\nbreak");
+				Ok(context)
+			})
+		})],
+	);
+	let client = TestClient::new(app);
+
+	client
+		.get("/invalid")
+		.send()
+		.await
+		.assert_status(500)
+		.assert_body("Internal server error");
+}
+
+#[cfg(feature = "tracing")]
+#[tokio::test]
+async fn the_traceparent_of_the_request_span_is_sent_back() {
+	let mut app = create_app();
+	app.get(
+		"/",
+		[DefaultMiddleware::new(|context, _| {
+			Box::pin(async move { Ok(context) })
+		})],
+	);
+	let client = TestClient::new(app);
+
+	let response = client
+		.get("/")
+		.header(
+			"traceparent",
+			"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+		)
+		.send()
+		.await;
+	response.assert_status(200);
+	let traceparent =
+		response.get_response().get_header("traceparent").unwrap();
+	assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+	assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+	let response = client.get("/missing").send().await;
+	response.assert_status(404);
+	assert!(response.get_response().get_header("traceparent").is_some());
+}
+
+struct User(&'static str);
+
+#[tokio::test]
+async fn extensions_are_shared_between_middlewares() {
+	let mut app = create_app();
+	app.get(
+		"/",
+		[
+			DefaultMiddleware::new(|mut context, next| {
+				Box::pin(async move {
+					context.extension(User("first"));
+					next(context).await
+				})
+			}),
+			DefaultMiddleware::new(|mut context, _| {
+				Box::pin(async move {
+					let user =
+						context.get_extension::<User>().map(|user| user.0);
+					context.body(user.unwrap_or("none"));
+					Ok(context)
+				})
+			}),
+		],
+	);
+	app.get(
+		"/replaced",
+		[
+			DefaultMiddleware::new(|mut context, next| {
+				Box::pin(async move {
+					context.extension(User("first"));
+					context.extension(User("second"));
+					next(context).await
+				})
+			}),
+			DefaultMiddleware::new(|mut context, _| {
+				Box::pin(async move {
+					let user =
+						context.get_extension::<User>().map(|user| user.0);
+					context.body(user.unwrap_or("none"));
+					Ok(context)
+				})
+			}),
+		],
+	);
+	let client = TestClient::new(app);
+
+	client.get("/").send().await.assert_body("first");
+	client.get("/replaced").send().await.assert_body("second");
+}
+
+#[derive(Debug)]
+struct RenderContext {
+	request: Request,
+	response: Response,
+	register: Arc<Handlebars<'static>>,
+}
+
+impl Context for RenderContext {
+	fn get_request(&self) -> &Request {
+		&self.request
+	}
+
+	fn get_request_mut(&mut self) -> &mut Request {
+		&mut self.request
+	}
+
+	fn get_response(&self) -> &Response {
+		&self.response
+	}
+
+	fn take_response(self) -> Response {
+		self.response
+	}
+
+	fn get_response_mut(&mut self) -> &mut Response {
+		&mut self.response
+	}
+}
+
+impl RenderEngine for RenderContext {
+	fn get_register(&self) -> &Arc<Handlebars<'_>> {
+		&self.register
+	}
+
+	fn set_register(&mut self, register: Arc<Handlebars<'static>>) {
+		self.register = register;
+	}
+}
+
+type RenderHandler = fn(
+	RenderContext,
+	NextHandler<RenderContext, ()>,
+) -> Pin<
+	Box<dyn Future<Output = Result<RenderContext, Error<()>>> + Send>,
+>;
+
+#[derive(Clone)]
+struct RenderMiddleware(RenderHandler);
+
+#[async_trait::async_trait]
+impl Middleware<RenderContext, ()> for RenderMiddleware {
+	async fn run_middleware(
+		&self,
+		context: RenderContext,
+		next: NextHandler<RenderContext, ()>,
+	) -> Result<RenderContext, Error<()>> {
+		(self.0)(context, next).await
+	}
+}
+
+fn create_render_app(
+) -> App<RenderContext, RenderMiddleware, Arc<Handlebars<'static>>, ()> {
+	let mut register = Handlebars::new();
+	register
+		.register_template_string("greeting", "{{greeting}}, {{name}}")
+		.unwrap();
+	let mut app = App::create(
+		|request, register: &Arc<Handlebars<'static>>| RenderContext {
+			request,
+			response: Response::new(),
+			register: register.clone(),
+		},
+		Arc::new(register),
+	);
+	app.use_middleware(
+		"/",
+		[RenderMiddleware(|mut context, next| {
+			Box::pin(async move {
+				context.local("greeting", "Hello").local("name", "local");
+				next(context).await
+			})
+		})],
+	);
+	app
+}
+
+#[tokio::test]
+async fn rendered_data_takes_precedence_over_locals() {
+	let mut app = create_render_app();
+	app.get(
+		"/",
+		[RenderMiddleware(|mut context, _| {
+			Box::pin(async move {
+				context.render("greeting", &json!({ "name": "data" }))?;
+				Ok(context)
+			})
+		})],
+	);
+	let client = TestClient::new(app);
+
+	client
+		.get("/")
+		.send()
+		.await
+		.assert_status(200)
+		.assert_body("Hello, data");
+}
+
+#[tokio::test]
+async fn locals_are_rendered_without_data() {
+	let mut app = create_render_app();
+	app.get(
+		"/none",
+		[RenderMiddleware(|mut context, _| {
+			Box::pin(async move {
+				context.render("greeting", &None::<()>)?;
+				Ok(context)
+			})
+		})],
+	);
+	app.get(
+		"/unit",
+		[RenderMiddleware(|mut context, _| {
+			Box::pin(async move {
+				context.render("greeting", &())?;
+				Ok(context)
+			})
+		})],
+	);
+	let client = TestClient::new(app);
+
+	client.get("/none").send().await.assert_body("Hello, local");
+	client.get("/unit").send().await.assert_body("Hello, local");
+}